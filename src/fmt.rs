@@ -1,27 +1,29 @@
-use std::{fmt::Display, fs::File, io::{self, Write}, path::PathBuf};
-use std::collections::VecDeque;
+use std::{collections::{HashMap, HashSet}, fmt::{self, Display, Formatter}, fs::File, io::{self, Write}, path::PathBuf};
 
 use crate::ley::*;
-use super::catch;
 
 pub struct Page {
     pub location: std::string::String,
-    pub title: std::string::String
+    pub title: std::string::String,
+    pub date: Option<Date>
 }
 
-pub trait Format<'a>: Display + From<Ley<'a>> + std::ops::Deref<Target=Ley<'a>> {
-    const EXTENSION: &'static str;
-    fn index(target: PathBuf, pages: &'a [Page], style: Metadata) -> Option<&'static str> {
+impl<'a> Ley<'a> {
+    /// A ley file with a link to each of `pages`
+    pub fn index(pages: &'a [Page], style: Metadata) -> Self {
         let mut ley = Ley {
             title: Some("Index".to_string()).into(),
-            author: None.into(),
-            date: None.into(),
+            author: Metadata::NONE,
+            date: Metadata::NONE,
             style,
+            metadata: Vec::new(),
+            anchors: HashMap::new(),
             lines: LeyLines(vec![
                 LeyLine::Section {
                     name: Some(String::new("Index")),
                     kind: SectionKind::Section,
-                    contents: LeyLines(vec![])
+                    contents: LeyLines(vec![]),
+                    span: Span::default()
                 }
             ])
         };
@@ -36,20 +38,388 @@ pub trait Format<'a>: Display + From<Ley<'a>> + std::ops::Deref<Target=Ley<'a>>
                             kind: SectionKind::Link,
                             contents: LeyLines(vec![
                                 LeyLine::Text {
-                                    contents: String::new(page.title.as_str())
+                                    contents: String::new(page.title.as_str()),
+                                    span: Span::default()
                                 }
-                            ])
+                            ]),
+                            span: Span::default()
                         }
-                    ])
+                    ]),
+                    span: Span::default()
                 }
             );
         }
-        if let Err(error) = Self::from(ley).render("index", target) {
-            Some(error)
+        ley
+    }
+    /// Find the section each `ref` section links to, giving an error for each that doesn't exist,
+    /// or otherwise the ids of the headings they link to in other files, to be kept in `anchors`.
+    /// Other files are looked up in `files`, by name without an extension, and only checked if it isn't empty,
+    /// as when a single file is converted
+    pub fn resolve_references(&self, files: &[(&str, &Ley)]) -> Result<HashMap<std::string::String, std::string::String>, Vec<ParseError<'a>>> {
+        /// The outlines of this file and of the other files referred to, each found the first time it is needed
+        struct Outlines<'l, 'a> {
+            document: Outline<'l, 'a>,
+            files: &'l [(&'l str, &'l Ley<'l>)],
+            others: HashMap<&'l str, Outline<'l, 'l>>,
+            anchors: HashMap<std::string::String, std::string::String>
+        }
+        fn walk<'a>(lines: &LeyLines<'a>, outlines: &mut Outlines, errors: &mut Vec<ParseError<'a>>) {
+            for ley_line in lines.iter() {
+                match ley_line {
+                    LeyLine::Section { name: Some(target), kind: SectionKind::Ref, span, .. } => {
+                        let target = target.to_string();
+                        let found = match target.split_once('#') {
+                            Some(_) if outlines.files.is_empty() => true,
+                            Some((file, name)) => match outlines.files.iter().find(|(other, _)| *other == file) {
+                                Some((file, ley)) => match outlines.others.entry(file).or_insert_with(|| Outline::new(ley)).referenced(name) {
+                                    Some(heading) => {
+                                        outlines.anchors.insert(target.clone(), heading.anchor.clone());
+                                        true
+                                    },
+                                    None => false
+                                },
+                                None => false
+                            },
+                            None => outlines.document.referenced(&target).is_some()
+                        };
+                        if !found {
+                            errors.push(ParseErrorKind::UnknownReference(target).at(*span))
+                        }
+                    },
+                    LeyLine::Section { contents, .. } | LeyLine::Inline { contents, .. } => walk(contents, outlines, errors),
+                    LeyLine::Text { .. } | LeyLine::Comment { .. } => ()
+                }
+            }
+        }
+        let mut outlines = Outlines { document: Outline::new(self), files, others: HashMap::new(), anchors: HashMap::new() };
+        let mut errors = Vec::new();
+        walk(&self.lines, &mut outlines, &mut errors);
+        if errors.is_empty() {
+            Ok(outlines.anchors)
         } else {
-            None
+            Err(errors)
+        }
+    }
+}
+
+/// How many levels of sections a table of contents lists if it doesn't give a `depth`
+const TOC_DEPTH: usize = 3;
+
+/// A named section, as listed in a table of contents
+pub(crate) struct Heading<'l, 'a> {
+    /// How deeply the section is nested, counting from 1
+    pub depth: usize,
+    /// The position of the section among its siblings at each depth, such as `[1, 2]` for `1.2`
+    pub number: Vec<usize>,
+    pub name: &'l String<'a>,
+    /// The id the section's heading is linked to by, unique within the document
+    pub anchor: std::string::String,
+    section: &'l LeyLine<'a>
+}
+/// The named sections and footnotes of a document, found once when it is rendered so that each can be looked up without walking the document again
+pub(crate) struct Outline<'l, 'a> {
+    pub headings: Vec<Heading<'l, 'a>>,
+    /// The footnotes in the order they are referred to, including any within other footnotes
+    pub footnotes: Vec<&'l LeyLine<'a>>,
+    /// The position in `headings` of each named section, by its address
+    sections: HashMap<*const LeyLine<'a>, usize>,
+    /// The position in `headings` of the first section with each name
+    names: HashMap<std::string::String, usize>,
+    /// The number of each footnote, by its address
+    numbers: HashMap<*const LeyLine<'a>, usize>,
+    /// The ids of the headings of other files that are linked to
+    anchors: &'l HashMap<std::string::String, std::string::String>
+}
+impl<'l, 'a> Outline<'l, 'a> {
+    pub fn new(ley: &'l Ley<'a>) -> Self {
+        let lines = &ley.lines;
+        let footnotes = footnotes(lines);
+        let headings = headings(lines, footnotes.len());
+        let sections = headings.iter().enumerate().map(|(index, heading)| (heading.section as *const _, index)).collect();
+        let mut names = HashMap::new();
+        for (index, heading) in headings.iter().enumerate() {
+            names.entry(heading.name.to_string()).or_insert(index);
         }
+        let numbers = footnotes.iter().enumerate().map(|(index, footnote)| (*footnote as *const _, index + 1)).collect();
+        Self { headings, footnotes, sections, names, numbers, anchors: &ley.anchors }
     }
+    /// The number of `footnote`, counting from 1
+    pub fn footnote_number(&self, footnote: &LeyLine) -> usize {
+        self.numbers.get(&(footnote as *const LeyLine)).copied().unwrap_or(0)
+    }
+    /// The heading of `section`, if it is a named section of the document
+    pub fn heading(&self, section: &LeyLine) -> Option<&Heading<'l, 'a>> {
+        self.sections.get(&(section as *const LeyLine)).map(|index| &self.headings[*index])
+    }
+    /// The heading of the section named `target`
+    pub fn referenced(&self, target: &str) -> Option<&Heading<'l, 'a>> {
+        self.names.get(target).map(|index| &self.headings[*index])
+    }
+    /// The headings as far down as the `toc` section with `contents` lists
+    pub fn toc(&self, contents: &LeyLines) -> impl Iterator<Item = &Heading<'l, 'a>> {
+        let depth = toc_depth(contents);
+        self.headings.iter().filter(move |heading| heading.depth <= depth)
+    }
+    /// Where the `ref` section named `target` links to
+    pub fn reference(&self, target: &String) -> Reference {
+        let target = target.to_string();
+        match target.split_once('#') {
+            // Without the other file at hand, its heading has this id unless an earlier one there has the same
+            Some((file, name)) => Reference {
+                anchor: self.anchors.get(&target).cloned().unwrap_or_else(|| anchor(name)),
+                file: Some(file.to_string()),
+                name: name.to_string()
+            },
+            None => Reference {
+                anchor: self.referenced(&target).map_or_else(|| anchor(&target), |heading| heading.anchor.clone()),
+                file: None,
+                name: target
+            }
+        }
+    }
+}
+/// Every named section of a document with `footnotes` footnotes, in order
+fn headings<'l, 'a>(lines: &'l LeyLines<'a>, footnotes: usize) -> Vec<Heading<'l, 'a>> {
+    fn walk<'l, 'a>(lines: &'l LeyLines<'a>, depth: usize, number: &mut Vec<usize>, used: &mut HashSet<std::string::String>, headings: &mut Vec<Heading<'l, 'a>>) {
+        for ley_line in lines.iter() {
+            match ley_line {
+                LeyLine::Section { name: Some(name), kind: SectionKind::Section, contents, .. } => {
+                    number.resize(depth, 0);
+                    number[depth - 1] += 1;
+                    let slug = anchor(&name.to_string());
+                    let mut anchor = slug.clone();
+                    let mut count = 1;
+                    while used.contains(&anchor) {
+                        count += 1;
+                        anchor = format!("{}-{}", slug, count)
+                    }
+                    used.insert(anchor.clone());
+                    headings.push(Heading { depth, number: number.clone(), name, anchor, section: ley_line });
+                    walk(contents, depth + 1, number, used, headings)
+                },
+                LeyLine::Section { kind: SectionKind::Section | SectionKind::Paragraph, contents, .. } => walk(contents, depth, number, used, headings),
+                _ => ()
+            }
+        }
+    }
+    // The ids of footnotes are taken first, so that a heading such as `Footnote 1` doesn't share one
+    let mut used: HashSet<_> = (1..=footnotes)
+        .flat_map(|number| IntoIterator::into_iter([format!("footnote-{}", number), format!("footnote-ref-{}", number)]))
+        .collect();
+    let mut headings = Vec::new();
+    walk(lines, 1, &mut Vec::new(), &mut used, &mut headings);
+    headings
+}
+/// How many levels of sections the `toc` section with `contents` lists
+pub(crate) fn toc_depth(contents: &LeyLines) -> usize {
+    contents.attribute("depth").and_then(|depth| depth.to_string().parse().ok()).unwrap_or(TOC_DEPTH)
+}
+/// An item of a `list` or `ordered` section
+pub(crate) struct Item<'l, 'a> {
+    /// The ley lines of the item, each with whether it was followed by whitespace
+    pub lines: Vec<(&'l LeyLine<'a>, bool)>,
+    /// The lists nested within the item
+    pub lists: Vec<&'l LeyLine<'a>>
+}
+/// The items of the list with `contents`: each unnamed section is an item, as is each run of other lines,
+/// and a nested list belongs to the item before it
+pub(crate) fn items<'l, 'a>(contents: &'l LeyLines<'a>) -> Vec<Item<'l, 'a>> {
+    let mut items: Vec<Item> = Vec::new();
+    // Whether the last item is a run of lines that the next line continues
+    let mut run = false;
+    for (ley_line, spaced) in contents.spaced() {
+        match ley_line {
+            LeyLine::Comment { .. } | LeyLine::Section { kind: SectionKind::Metadata, .. } => continue,
+            LeyLine::Section { kind: SectionKind::List | SectionKind::Ordered, .. } => {
+                match items.last_mut() {
+                    Some(item) => item.lists.push(ley_line),
+                    None => items.push(Item { lines: Vec::new(), lists: vec![ley_line] })
+                }
+                run = false
+            },
+            LeyLine::Section { name: None, kind: SectionKind::Section | SectionKind::Paragraph, contents, .. } => {
+                items.push(Item { lines: contents.spaced().collect(), lists: Vec::new() });
+                run = false
+            },
+            _ => {
+                match items.last_mut() {
+                    Some(item) if run => item.lines.push((ley_line, spaced)),
+                    _ => items.push(Item { lines: vec![(ley_line, spaced)], lists: Vec::new() })
+                }
+                run = true
+            }
+        }
+    }
+    items
+}
+/// The number the `ordered` section with `contents` counts from
+pub(crate) fn list_start(contents: &LeyLines) -> usize {
+    contents.attribute("start").and_then(|start| start.to_string().parse().ok()).unwrap_or(1)
+}
+/// How the contents of a column of a table are aligned
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Alignment {
+    Left,
+    Center,
+    Right
+}
+/// A row of a `table` section
+pub(crate) struct Row<'l, 'a> {
+    /// Whether the cells head their columns
+    pub header: bool,
+    /// The contents of each cell, with how many columns it spans
+    pub cells: Vec<(&'l LeyLines<'a>, usize)>
+}
+/// The rows of a `table` section, and its columns
+pub(crate) struct Table<'l, 'a> {
+    pub rows: Vec<Row<'l, 'a>>,
+    /// The alignment of each column, if it is given
+    pub alignments: Vec<Option<Alignment>>
+}
+/// The table with `contents`
+pub(crate) fn table<'l, 'a>(contents: &'l LeyLines<'a>) -> Table<'l, 'a> {
+    let rows: Vec<_> = contents.iter().filter_map(|ley_line| match ley_line {
+        LeyLine::Section { kind: kind @ (SectionKind::Row | SectionKind::Header), contents, .. } => Some(Row {
+            header: *kind == SectionKind::Header,
+            cells: contents.iter().filter_map(|ley_line| match ley_line {
+                LeyLine::Section { kind: SectionKind::Cell, contents, .. } => {
+                    // A span of 0 is rejected when parsed, but is kept from leaving a cell with no columns all the same
+                    let span = contents.attribute("colspan").and_then(|span| span.to_string().parse().ok()).unwrap_or(1);
+                    Some((contents, usize::max(span, 1)))
+                },
+                _ => None
+            }).collect()
+        }),
+        _ => None
+    }).collect();
+    let columns = rows.iter().map(|row| row.cells.iter().map(|(_, span)| span).sum()).max().unwrap_or(0);
+    let alignments = contents.attribute("align").map(|alignments| alignments.to_string()).unwrap_or_default();
+    let mut alignments: Vec<_> = alignments.split(' ').filter(|alignment| !alignment.is_empty()).map(|alignment| match alignment {
+        "center" => Some(Alignment::Center),
+        "right" => Some(Alignment::Right),
+        _ => Some(Alignment::Left)
+    }).collect();
+    alignments.resize(columns, None);
+    Table { rows, alignments }
+}
+impl<'l, 'a> Table<'l, 'a> {
+    /// The number of columns, which is the most that any row spans
+    pub fn columns(&self) -> usize {
+        self.alignments.len()
+    }
+}
+/// The footnotes of a document in the order they are referred to, including any within other footnotes
+fn footnotes<'l, 'a>(lines: &'l LeyLines<'a>) -> Vec<&'l LeyLine<'a>> {
+    fn walk<'l, 'a>(lines: &'l LeyLines<'a>, footnotes: &mut Vec<&'l LeyLine<'a>>) {
+        for ley_line in lines.iter() {
+            match ley_line {
+                LeyLine::Section { kind: SectionKind::Footnote, contents, .. } => {
+                    footnotes.push(ley_line);
+                    walk(contents, footnotes)
+                },
+                LeyLine::Section { contents, .. } | LeyLine::Inline { contents, .. } => walk(contents, footnotes),
+                LeyLine::Text { .. } | LeyLine::Comment { .. } => ()
+            }
+        }
+    }
+    let mut footnotes = Vec::new();
+    walk(lines, &mut footnotes);
+    footnotes
+}
+/// The id of the heading of the section called `name`, before it is made unique: its letters and digits in lowercase,
+/// with each run of anything else replaced by `-`
+pub(crate) fn anchor(name: &str) -> std::string::String {
+    let mut anchor = std::string::String::new();
+    for word in name.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+        if !anchor.is_empty() {
+            anchor.push('-')
+        }
+        anchor.extend(word.chars().flat_map(char::to_lowercase))
+    }
+    if anchor.is_empty() {
+        anchor.push_str("section")
+    }
+    anchor
+}
+/// Where a `ref` section links to
+pub(crate) struct Reference {
+    /// The file the section is in, without an extension, if it isn't this one
+    pub file: Option<std::string::String>,
+    /// The name of the section, shown when the reference has no contents
+    pub name: std::string::String,
+    pub anchor: std::string::String
+}
+impl Reference {
+    /// The url of the section, for a format whose files have `extension`
+    pub fn url(&self, extension: &str) -> std::string::String {
+        match &self.file {
+            Some(file) => format!("{}.{}#{}", file, extension, self.anchor),
+            None => format!("#{}", self.anchor)
+        }
+    }
+}
+
+/// Output written as blocks set apart by blank lines, with the inline lines within them spaced as they were in the source
+pub(crate) struct Blocks<'a, 'b> {
+    pub f: &'a mut Formatter<'b>,
+    /// Whether a block has been written, which the next is set apart from
+    pub started: bool,
+    /// Whether the last inline line was followed by whitespace
+    pub space: bool
+}
+impl<'a, 'b> Blocks<'a, 'b> {
+    pub fn new(f: &'a mut Formatter<'b>) -> Self {
+        Self { f, started: false, space: false }
+    }
+    /// Begin the next block, after a blank line if it isn't the first
+    pub fn start(&mut self) -> fmt::Result {
+        if self.started {
+            self.f.write_str("\n\n")?
+        }
+        self.started = true;
+        self.space = false;
+        Ok(())
+    }
+    /// End the output, with a newline after the last block if there is one
+    pub fn finish(&mut self) -> fmt::Result {
+        if self.started {
+            self.f.write_str("\n")?
+        }
+        Ok(())
+    }
+}
+
+/// The inline lines for which `shown` holds, with whether a space goes before each as it did in the source.
+/// Lines that aren't shown mustn't leave their spacing behind, as an attribute at the start of a cell with a `colspan` would
+pub(crate) fn inline<'l, 'a>(lines: &'l LeyLines<'a>, shown: fn(&LeyLine) -> bool) -> impl Iterator<Item = (&'l LeyLine<'a>, bool)> {
+    let (mut written, mut space) = (false, false);
+    lines.spaced().filter_map(move |(ley_line, spaced)| {
+        if !shown(ley_line) {
+            space |= spaced;
+            return None
+        }
+        let before = written && space;
+        written = true;
+        space = spaced;
+        Some((ley_line, before))
+    })
+}
+/// Whether a line is shown inline, as all but comments and attributes are
+pub(crate) fn is_shown(ley_line: &LeyLine) -> bool {
+    !matches!(ley_line, LeyLine::Comment { .. } | LeyLine::Section { kind: SectionKind::Metadata, .. })
+}
+
+pub trait Format<'a>: Display + From<Ley<'a>> + std::ops::Deref<Target=Ley<'a>> {
+    const EXTENSION: &'static str;
+    /// Write an index linking to `pages` to the `target` directory
+    fn index(target: PathBuf, pages: &'a [Page], style: Metadata) -> Option<&'static str> {
+        Self::from(Ley::index(pages, style)).render("index", target).err()
+    }
+    /// Write the formatted ley file to `writer`
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "{}", self)
+    }
+    /// Write the formatted ley file to `name` in the `target` directory, with the extension of the format
     fn render(&self, name: &str, target: PathBuf) -> Result<Page, &'static str> {
         let mut file_name = name.to_string();
         file_name.push('.');
@@ -63,13 +433,56 @@ pub trait Format<'a>: Display + From<Ley<'a>> + std::ops::Deref<Target=Ley<'a>>
             return Err("File could not be opened with write permissions")
         };
 
-        if write!(ley_destination, "{}", self).is_err() {
+        if self.write_to(&mut ley_destination).is_err() {
             Err("Unable to write to destination file")
         } else {
             Ok(Page {
                 location: file_name,
-                title: self.title.default("Untitled").to_string()
+                title: self.title.default("Untitled").to_string(),
+                date: self.date.as_date()
             })
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn anchors() {
+        let ley = Ley::new("!Intro: {} !\"Intro!\": {} !intro 2: {} !Usage: {!Intro: {}} !Footnote 1: {a note!: footnote {here}} !\"!?\": {}", None.into()).unwrap();
+        let outline = Outline::new(&ley);
+        let anchors: Vec<_> = outline.headings.iter().map(|heading| heading.anchor.as_str()).collect();
+        assert_eq!(anchors, ["intro", "intro-2", "intro-2-2", "usage", "intro-3", "footnote-1-2", "section"]);
+        // A name shared by several sections refers to the first
+        assert_eq!(outline.referenced("Intro").unwrap().anchor, "intro");
+        assert_eq!(outline.reference(&String::split("Intro!")).anchor, "intro-2");
+    }
+
+    #[test]
+    fn references_to_other_files() {
+        let guide = Ley::new("!\"Setup!\": {} !Setup: {}", None.into()).unwrap();
+        let ley = Ley::new("see !\"guide#Setup\": ref {} and !Intro: ref {}", None.into()).unwrap();
+        let errors = ley.resolve_references(&[("guide", &guide)]).unwrap_err();
+        assert!(matches!(errors.as_slice(), [ParseError { kind: ParseErrorKind::UnknownReference(target), .. }] if target == "Intro"));
+
+        let mut ley = Ley::new("see !\"guide#Setup\": ref {}", None.into()).unwrap();
+        ley.anchors = ley.resolve_references(&[("guide", &guide)]).unwrap();
+        assert_eq!(Outline::new(&ley).reference(&String::split("guide#Setup")).url("html"), "guide.html#setup-2");
+        // Without the other file it can only be assumed that no earlier heading there has the same id
+        assert!(ley.resolve_references(&[]).unwrap().is_empty());
+        ley.anchors.clear();
+        assert_eq!(Outline::new(&ley).reference(&String::split("guide#Setup")).url("html"), "guide.html#setup")
+    }
+
+    #[test]
+    fn inline_spacing() {
+        // Lines that aren't shown take the space before them along, but leave none at the start
+        let ley = Ley::new("!; {a} !colspan: meta {2} b!; {c} c !; {d}d", None.into()).unwrap();
+        let lines: Vec<_> = inline(&ley.lines, is_shown).map(|(ley_line, space)| match ley_line {
+            LeyLine::Text { contents, .. } => (contents.to_string(), space),
+            _ => panic!("Expected text")
+        }).collect();
+        assert_eq!(lines, [("b".to_string(), false), ("c".to_string(), true), ("d".to_string(), true)]);
+    }
+}
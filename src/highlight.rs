@@ -0,0 +1,343 @@
+use crate::ley::Token;
+
+/// What a highlighted piece of code is, rendered by `Html` as a span with the class `hl-{name}`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Class {
+    Keyword,
+    Type,
+    Literal,
+    String,
+    Number,
+    Comment,
+    Variable,
+    Punctuation
+}
+impl Class {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Keyword => "keyword",
+            Self::Type => "type",
+            Self::Literal => "literal",
+            Self::String => "string",
+            Self::Number => "number",
+            Self::Comment => "comment",
+            Self::Variable => "variable",
+            Self::Punctuation => "punctuation"
+        }
+    }
+}
+
+/// Split `code` into pieces that are each either highlighted with a class or plain.
+/// Returns `None` if the language isn't known
+pub fn highlight<'a>(language: &str, code: &'a str) -> Option<Vec<(Option<Class>, &'a str)>> {
+    let language = language.to_ascii_lowercase();
+    let grammar = match language.as_str() {
+        "rust" | "rs" => &RUST,
+        "c" | "h" => &C,
+        "python" | "py" => &PYTHON,
+        "shell" | "sh" | "bash" | "zsh" => &SHELL,
+        "json" => &JSON,
+        "ley" => return Some(highlight_ley(code)),
+        _ => return None
+    };
+    Some(grammar.highlight(code))
+}
+
+/// The lexical rules of a language, enough to find its keywords, literals and comments
+struct Grammar {
+    keywords: &'static [&'static str],
+    types: &'static [&'static str],
+    literals: &'static [&'static str],
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// A character that starts a variable, such as `$` in shell
+    variable: Option<char>,
+    /// A character that starts a directive keyword, such as `#` in C
+    directive: Option<char>,
+    /// Whether `'` starts a lifetime, unless it is a character literal
+    lifetimes: bool
+}
+impl Grammar {
+    fn highlight<'a>(&self, code: &'a str) -> Vec<(Option<Class>, &'a str)> {
+        let mut pieces = Vec::new();
+        let mut plain = 0;
+        let mut pos = 0;
+        while pos < code.len() {
+            let rest = &code[pos..];
+            let c = rest.chars().next().unwrap_or_default();
+            let piece = if let Some(comment) = self.line_comment.filter(|comment| rest.starts_with(*comment)) {
+                Some((Class::Comment, rest.find('\n').unwrap_or(rest.len()).max(comment.len())))
+            } else if let Some((open, close)) = self.block_comment.filter(|(open, _)| rest.starts_with(*open)) {
+                Some((Class::Comment, rest[open.len()..].find(close).map_or(rest.len(), |end| open.len() + end + close.len())))
+            } else if c == '\'' && self.lifetimes {
+                if is_char_literal(rest) {
+                    Some((Class::String, string_length(rest, c)))
+                } else {
+                    Some((Class::Type, 1 + word_length(&rest[1..])))
+                }
+            } else if self.quotes.contains(&c) {
+                Some((Class::String, string_length(rest, c)))
+            } else if Some(c) == self.directive {
+                Some((Class::Keyword, 1 + word_length(&rest[1..])))
+            } else if Some(c) == self.variable {
+                let length = match rest[1..].chars().next() {
+                    Some('{') => rest.find('}').map_or(rest.len(), |end| end + 1),
+                    Some(c) if !(c.is_alphanumeric() || c == '_') => 1 + c.len_utf8(),
+                    _ => 1 + word_length(&rest[1..])
+                };
+                Some((Class::Variable, length))
+            } else if c.is_ascii_digit() {
+                Some((Class::Number, number_length(rest)))
+            } else if c.is_alphabetic() || c == '_' {
+                let length = word_length(rest);
+                let word = &rest[..length];
+                if self.keywords.contains(&word) {
+                    Some((Class::Keyword, length))
+                } else if self.types.contains(&word) {
+                    Some((Class::Type, length))
+                } else if self.literals.contains(&word) {
+                    Some((Class::Literal, length))
+                } else {
+                    pos += length;
+                    continue
+                }
+            } else {
+                None
+            };
+            match piece {
+                Some((class, length)) => {
+                    if plain < pos {
+                        pieces.push((None, &code[plain..pos]))
+                    }
+                    pieces.push((Some(class), &rest[..length]));
+                    pos += length;
+                    plain = pos
+                }
+                None => pos += c.len_utf8()
+            }
+        }
+        if plain < code.len() {
+            pieces.push((None, &code[plain..]))
+        }
+        pieces
+    }
+}
+
+/// The length of the identifier or number at the start of `code`
+fn word_length(code: &str) -> usize {
+    code.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(code.len())
+}
+/// The length of the number at the start of `code`, including any fractional part
+fn number_length(code: &str) -> usize {
+    let length = word_length(code);
+    match code[length..].strip_prefix('.') {
+        Some(fraction) if fraction.starts_with(|c: char| c.is_ascii_digit()) => length + 1 + word_length(fraction),
+        _ => length
+    }
+}
+/// The length of the string at the start of `code` opened with `quote`, including the quotes
+fn string_length(code: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (index, c) in code.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return index + c.len_utf8(),
+            _ => ()
+        }
+    }
+    code.len()
+}
+/// Whether the `'` at the start of `code` opens a character literal such as `'a'` or `'\n'`
+fn is_char_literal(code: &str) -> bool {
+    let mut chars = code.chars().skip(1);
+    match chars.next() {
+        Some('\\') => true,
+        Some(_) => chars.next() == Some('\''),
+        None => false
+    }
+}
+
+/// Highlight ley using its own lexer
+fn highlight_ley(code: &str) -> Vec<(Option<Class>, &str)> {
+    let tokens = Token::tokenize(code, &mut Vec::new());
+    let mut pieces = Vec::new();
+    let mut plain = 0;
+    let mut push = |class, start: usize, end: usize, pieces: &mut Vec<_>| {
+        if plain < start {
+            pieces.push((None, &code[plain..start]))
+        }
+        pieces.push((Some(class), &code[start..end]));
+        plain = end
+    };
+    let mut index = 0;
+    while let Some(token) = tokens.get(index) {
+        match token.value {
+            Token::Exclamation => {
+                // The header of a section, `!name: kind {`
+                let start = token.span.start;
+                let separator = tokens[index + 1..].iter().position(|token| !matches!(token.value, Token::Identifier(_))).map(|offset| index + 1 + offset);
+                match separator.map(|separator| (separator, &tokens[separator].value)) {
+                    Some((separator, Token::SemiColon)) => {
+                        // A comment, up to the end of its body
+                        let mut depth = 0;
+                        let mut end = tokens[separator].span.end;
+                        index = separator + 1;
+                        while let Some(token) = tokens.get(index) {
+                            end = token.span.end;
+                            index += 1;
+                            match token.value {
+                                Token::OpenBrace => depth += 1,
+                                Token::CloseBrace if depth <= 1 => break,
+                                Token::CloseBrace => depth -= 1,
+                                _ => ()
+                            }
+                        }
+                        push(Class::Comment, start, end, &mut pieces);
+                        continue
+                    }
+                    Some((separator, Token::Colon)) => {
+                        push(Class::Punctuation, start, start + 1, &mut pieces);
+                        if separator > index + 1 {
+                            push(Class::String, tokens[index + 1].span.start, tokens[separator - 1].span.end, &mut pieces)
+                        }
+                        push(Class::Punctuation, tokens[separator].span.start, tokens[separator].span.end, &mut pieces);
+                        index = separator + 1;
+                        if let Some(kind) = tokens.get(index).filter(|token| matches!(token.value, Token::Identifier(_))) {
+                            push(Class::Keyword, kind.span.start, kind.span.end, &mut pieces);
+                            index += 1
+                        }
+                        continue
+                    }
+                    _ => push(Class::Punctuation, start, token.span.end, &mut pieces)
+                }
+            }
+            Token::Identifier(_) => if code[token.span.start..].starts_with('"') {
+                push(Class::String, token.span.start, token.span.end, &mut pieces)
+            },
+            // The code within code sections isn't ley
+            Token::Verbatim(_) => (),
+            _ => push(Class::Punctuation, token.span.start, token.span.end, &mut pieces)
+        }
+        index += 1
+    }
+    if plain < code.len() {
+        pieces.push((None, &code[plain..]))
+    }
+    pieces
+}
+
+const RUST: Grammar = Grammar {
+    keywords: &[
+        "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn", "for", "if", "impl", "in",
+        "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct", "super", "trait", "type", "unsafe",
+        "use", "where", "while", "Self", "self"
+    ],
+    types: &[
+        "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64",
+        "String", "Vec", "Option", "Result", "Box"
+    ],
+    literals: &["true", "false", "Some", "None", "Ok", "Err"],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+    variable: None,
+    directive: None,
+    lifetimes: true
+};
+const C: Grammar = Grammar {
+    keywords: &[
+        "auto", "break", "case", "const", "continue", "default", "do", "else", "enum", "extern", "for", "goto", "if", "inline",
+        "register", "restrict", "return", "sizeof", "static", "struct", "switch", "typedef", "union", "volatile", "while"
+    ],
+    types: &[
+        "char", "double", "float", "int", "long", "short", "signed", "unsigned", "void", "bool", "size_t", "int8_t", "int16_t",
+        "int32_t", "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t"
+    ],
+    literals: &["NULL", "true", "false"],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    variable: None,
+    directive: Some('#'),
+    lifetimes: false
+};
+const PYTHON: Grammar = Grammar {
+    keywords: &[
+        "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except", "finally",
+        "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try",
+        "while", "with", "yield"
+    ],
+    types: &["int", "float", "str", "bytes", "bool", "list", "dict", "set", "tuple", "object"],
+    literals: &["True", "False", "None"],
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+    variable: None,
+    directive: None,
+    lifetimes: false
+};
+const SHELL: Grammar = Grammar {
+    keywords: &[
+        "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "in", "function", "return",
+        "export", "local", "readonly", "set", "unset", "shift", "exit", "source"
+    ],
+    types: &[],
+    literals: &["true", "false"],
+    line_comment: Some("#"),
+    block_comment: None,
+    quotes: &['"', '\''],
+    variable: Some('$'),
+    directive: None,
+    lifetimes: false
+};
+const JSON: Grammar = Grammar {
+    keywords: &[],
+    types: &[],
+    literals: &["true", "false", "null"],
+    line_comment: None,
+    block_comment: None,
+    quotes: &['"'],
+    variable: None,
+    directive: None,
+    lifetimes: false
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pieces `code` is highlighted as, with their class names
+    fn pieces<'a>(language: &str, code: &'a str) -> Vec<(&'static str, &'a str)> {
+        highlight(language, code).unwrap().into_iter().map(|(class, piece)| (class.map_or("", Class::name), piece)).collect()
+    }
+
+    #[test]
+    fn languages() {
+        assert_eq!(pieces("Rust", "let x: u8 = 'a' as u8; // 1"), [
+            ("keyword", "let"), ("", " x: "), ("type", "u8"), ("", " = "), ("string", "'a'"), ("", " "), ("keyword", "as"), ("", " "),
+            ("type", "u8"), ("", "; "), ("comment", "// 1")
+        ]);
+        assert_eq!(pieces("rs", "fn f<'a>(s: &'a str)")[..4], [("keyword", "fn"), ("", " f<"), ("type", "'a"), ("", ">(s: &")]);
+        assert_eq!(pieces("c", "#include <a.h>\n/* x */ return 1.5;"), [
+            ("keyword", "#include"), ("", " <a.h>\n"), ("comment", "/* x */"), ("", " "), ("keyword", "return"), ("", " "), ("number", "1.5"), ("", ";")
+        ]);
+        assert_eq!(pieces("py", "if x is None: print(\"a\\\"b\")"), [
+            ("keyword", "if"), ("", " x "), ("keyword", "is"), ("", " "), ("literal", "None"), ("", ": print("), ("string", "\"a\\\"b\""), ("", ")")
+        ]);
+        assert_eq!(pieces("sh", "echo $HOME ${PATH} $1 # done"), [
+            ("", "echo "), ("variable", "$HOME"), ("", " "), ("variable", "${PATH}"), ("", " "), ("variable", "$1"), ("", " "), ("comment", "# done")
+        ]);
+        assert_eq!(pieces("json", "{\"a\": [true, 2]}"), [("", "{"), ("string", "\"a\""), ("", ": ["), ("literal", "true"), ("", ", "), ("number", "2"), ("", "]}")]);
+        assert_eq!(highlight("brainfuck", "+[-]"), None);
+    }
+
+    #[test]
+    fn ley() {
+        assert_eq!(pieces("ley", "!Intro: section {text} !; comment {a {b}} c"), [
+            ("punctuation", "!"), ("string", "Intro"), ("punctuation", ":"), ("", " "), ("keyword", "section"), ("", " "), ("punctuation", "{"), ("", "text"),
+            ("punctuation", "}"), ("", " "), ("comment", "!; comment {a {b}}"), ("", " c")
+        ]);
+    }
+}
@@ -1,59 +1,471 @@
-use crate::{Ley, LeyLine, LeyLines, ley, Format};
+use crate::{Ley, LeyLine, LeyLines, ley::{self, MetadataValue}, fmt::{Alignment, Heading, Outline, inline, is_shown, items, table}, Format, Page, highlight::highlight, math::mathml, template::{Template, Value}};
 
-use std::{fmt::{self, Display, Formatter}, ops::Deref};
+use std::{fmt::{self, Display, Formatter, Write}, ops::Deref, sync::OnceLock};
 
-pub struct Html<'a>(pub Ley<'a>);
+pub struct Html<'a> {
+    ley: Ley<'a>,
+    safe: bool,
+    template: Option<&'a Template>,
+    pages: &'a [Page]
+}
+impl<'a> Html<'a> {
+    /// In safe mode, links and images with a url scheme other than `http`, `https` or `mailto` are not rendered as links or images.
+    /// Use for documents from untrusted sources, as `javascript:` urls can otherwise run arbitrary scripts
+    pub fn safe(mut self, safe: bool) -> Self {
+        self.safe = safe;
+        self
+    }
+    /// Fill in `template` instead of the default page template
+    pub fn template(mut self, template: &'a Template) -> Self {
+        self.template = Some(template);
+        self
+    }
+    /// The pages of the site, which templates can loop over as `pages`
+    pub fn pages(mut self, pages: &'a [Page]) -> Self {
+        self.pages = pages;
+        self
+    }
+    /// The template variable of a metadata value
+    fn variable(&self, value: &MetadataValue) -> Value {
+        match value {
+            MetadataValue::Bool(value) => Value::Bool(*value),
+            MetadataValue::List(items) => Value::List(items.iter().map(|item| vec![(".".to_string(), self.variable(item))]).collect()),
+            value => Value::Text(self.show(value))
+        }
+    }
+    /// Whether the url may be linked to in the current mode
+    fn allows(&self, url: &str) -> bool {
+        !self.safe || is_safe_url(url)
+    }
+}
 impl<'a> Format<'a> for Html<'a> {
     const EXTENSION: &'static str = "html";
 }
 impl<'a> From<Ley<'a>> for Html<'a> {
     fn from(ley: Ley<'a>) -> Self {
-        Self(ley)
+        Self {
+            ley,
+            safe: false,
+            template: None,
+            pages: &[]
+        }
     }
 }
 impl<'a> Display for Html<'a> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(
-            f,
-            include_str!("main.html"),
-            content = InnerHtml(&self.0.lines, 1),
-            title = self.title.default("Untitled Page"),
-            author = self.author.default("No Author"),
-            date = self.date.default("Unknown Date"),
-            style = self.style.default("main.css")
-        )
+        static DEFAULT: OnceLock<Template> = OnceLock::new();
+        let template = self.template.unwrap_or_else(|| DEFAULT.get_or_init(|| Template::parse(include_str!("main.html"), None).unwrap()));
+        let style = self.style.default("main.css");
+        // Every piece of metadata is a variable, though not one that would hide the content, style or pages
+        let mut variables: Vec<_> = self.ley.all_metadata().map(|(name, value)| (name.to_string(), self.variable(value))).collect();
+        let writer = Writer { html: self, outline: Outline::new(&self.ley) };
+        variables.push(("content".to_string(), Value::Html(format!("{}{}", InnerHtml(&writer, &self.ley.lines, 1), Footnotes(&writer)))));
+        variables.push(("style".to_string(), Value::Text(if self.allows(&style) { style.into_owned() } else { std::string::String::new() })));
+        let pages = self.pages.iter().map(|page| vec![
+            ("location".to_string(), Value::Text(page.location.clone())),
+            ("title".to_string(), Value::Text(page.title.clone())),
+            ("date".to_string(), Value::Text(page.date.map_or(std::string::String::new(), |date| date.format(&self.date_format()))))
+        ]).collect();
+        variables.push(("pages".to_string(), Value::List(pages)));
+        write!(f, "{}", template.fill(&variables))
     }
 }
 impl<'a> Deref for Html<'a> {
     type Target = Ley<'a>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.ley
     }
 }
 
-struct InnerHtml<'a>(pub &'a LeyLines<'a>, usize);
+/// The page being written, along with the outline of its document
+struct Writer<'a> {
+    html: &'a Html<'a>,
+    outline: Outline<'a, 'a>
+}
+impl<'a> Deref for Writer<'a> {
+    type Target = Html<'a>;
+    fn deref(&self) -> &Self::Target {
+        self.html
+    }
+}
+
+struct InnerHtml<'a>(&'a Writer<'a>, &'a LeyLines<'a>, usize);
 impl<'a> Display for InnerHtml<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let InnerHtml(html, lines, depth) = *self;
+        for (ley_line, space) in inline(lines, is_shown) {
+            if space {
+                f.write_str(" ")?
+            }
+            write!(f, "{}", Line(html, ley_line, depth))?
+        }
+        Ok(())
+    }
+}
+/// A single ley line, within `depth` levels of named sections
+struct Line<'a>(&'a Writer<'a>, &'a LeyLine<'a>, usize);
+impl<'a> Display for Line<'a> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         use LeyLine::*;
-        for ley_line in self.0.iter() {
-            use ley::SectionKind;
-            match ley_line {
-                Section { name: Some(name), contents, kind: SectionKind::Section} => write!(
+        use ley::SectionKind;
+        let Line(html, ley_line, depth) = *self;
+        match ley_line {
+            Section { name: Some(name), contents, kind: SectionKind::Section, .. } => write!(
+                f,
+                "<h{depth} id=\"{id}\">{name}</h{depth}><div class=\"depth_{depth}\">{contents}</div>",
+                id = EscapeAttribute(html.outline.heading(ley_line).map_or("", |heading| &heading.anchor)),
+                name = EscapeText(name),
+                contents = InnerHtml(html, contents, depth + 1),
+                depth = depth
+            )?,
+            Section { contents, kind: SectionKind::Paragraph, ..} | Section { name: None, contents, kind: SectionKind::Section, .. } => write!(f, "<p>{}</p>", InnerHtml(html, contents, depth))?,
+            Text { contents, .. } => write!(f, "{}", EscapeText(contents))?,
+            Inline { kind, contents, .. } => {
+                use ley::InlineKind;
+                let tag = match kind {
+                    InlineKind::Emphasis => "em",
+                    InlineKind::Strong => "strong",
+                    InlineKind::Underline => "u",
+                    InlineKind::Strikethrough => "s",
+                    InlineKind::Monospace => "code"
+                };
+                write!(f, "<{tag}>{contents}</{tag}>", tag = tag, contents = InnerHtml(html, contents, depth))?
+            },
+            Section { name: Some(name), contents, kind: SectionKind::Link, .. } if html.allows(&name.to_string()) => write!(f, "<a href=\"{name}\">{contents}</a>", name = EscapeAttribute(name), contents = InnerHtml(html, contents, depth))?,
+            Section { name, contents, kind: SectionKind::Code, .. } => write!(f, "<pre><code{language}>{code}</code></pre>", language = Language(name), code = Code(name, contents))?,
+            Section { name, contents, kind: SectionKind::InlineCode, .. } => write!(f, "<code{language}>{code}</code>", language = Language(name), code = Code(name, contents))?,
+            Section { name, contents, kind: kind @ (SectionKind::Math | SectionKind::InlineMath), .. } => write!(f, "{}", Math(name, contents, *kind == SectionKind::Math))?,
+            Section { contents, kind: SectionKind::Link, .. } => write!(f, "<a>{contents}</a>", contents = InnerHtml(html, contents, depth))?,
+            Section { name: Some(name), contents, kind: SectionKind::Image, .. } if html.allows(&name.to_string()) => {
+                let image = format!(
+                    "<img src=\"{src}\" alt=\"{alt}\"{width}{height}{title}>",
+                    src = EscapeAttribute(name),
+                    alt = EscapeAttribute(contents.plain()),
+                    width = Attribute("width", contents.attribute("width")),
+                    height = Attribute("height", contents.attribute("height")),
+                    title = Attribute("title", contents.attribute("title"))
+                );
+                match contents.attribute("caption") {
+                    Some(caption) => write!(f, "<figure>{}<figcaption>{}</figcaption></figure>", image, EscapeText(caption))?,
+                    None => f.write_str(&image)?
+                }
+            },
+            Section { kind: SectionKind::Footnote, .. } => write!(
+                f,
+                "<sup class=\"footnote\" id=\"footnote-ref-{number}\"><a href=\"#footnote-{number}\">{number}</a></sup>",
+                number = html.outline.footnote_number(ley_line)
+            )?,
+            Section { name, contents, kind: SectionKind::Toc, .. } => write!(f, "{}", Toc(name, html.outline.toc(contents).collect()))?,
+            Section { contents, kind: kind @ (SectionKind::List | SectionKind::Ordered), .. } => write!(f, "{}", List(html, *kind, contents, depth))?,
+            Section { contents, kind: SectionKind::Table, .. } => write!(f, "{}", Table(html, contents, depth))?,
+            // Rows and cells outside of a table have nothing to line up with
+            Section { contents, kind: SectionKind::Row | SectionKind::Header | SectionKind::Cell, .. } => write!(f, "{}", InnerHtml(html, contents, depth))?,
+            Section { name: Some(target), contents, kind: SectionKind::Ref, .. } => {
+                let reference = html.outline.reference(target);
+                let url = reference.url(Html::EXTENSION);
+                // Parsed references can only name files in the directory, but imported json may name anything
+                if html.allows(&url) {
+                    write!(f, "<a href=\"{}\">", EscapeAttribute(url))?
+                } else {
+                    f.write_str("<a>")?
+                }
+                if contents.is_empty() {
+                    write!(f, "{}", EscapeText(&reference.name))?
+                } else {
+                    write!(f, "{}", InnerHtml(html, contents, depth))?
+                }
+                f.write_str("</a>")?
+            },
+            Comment { .. } | Section { kind: SectionKind::Metadata | SectionKind::Image | SectionKind::Ref, ..} => ()
+        }
+        Ok(())
+    }
+}
+/// A `list` or `ordered` section, with the lists nested in an item kept within it
+struct List<'a>(&'a Writer<'a>, ley::SectionKind, &'a LeyLines<'a>, usize);
+impl<'a> Display for List<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let List(html, kind, contents, depth) = *self;
+        let tag = if kind == ley::SectionKind::Ordered { "ol" } else { "ul" };
+        write!(f, "<{}{}>", tag, Attribute("start", contents.attribute("start")))?;
+        for item in items(contents) {
+            f.write_str("<li>")?;
+            for (index, (ley_line, spaced)) in item.lines.iter().enumerate() {
+                write!(f, "{}", Line(html, ley_line, depth))?;
+                if *spaced && index + 1 < item.lines.len() {
+                    f.write_str(" ")?
+                }
+            }
+            for list in item.lists {
+                write!(f, "{}", Line(html, list, depth))?
+            }
+            f.write_str("</li>")?
+        }
+        write!(f, "</{}>", tag)
+    }
+}
+
+/// The footnotes of the page as a numbered list, each linking back to where it is referred to
+struct Footnotes<'a>(&'a Writer<'a>);
+impl<'a> Display for Footnotes<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let html = self.0;
+        let footnotes = &html.outline.footnotes;
+        if footnotes.is_empty() {
+            return Ok(())
+        }
+        f.write_str("<section class=\"footnotes\"><ol>")?;
+        for (index, footnote) in footnotes.iter().enumerate() {
+            if let LeyLine::Section { contents, .. } = footnote {
+                write!(
                     f,
-                    "<h{depth} id=\"{name}\">{name}</h{depth}><div class=\"depth_{depth}\">{contents}</div>",
-                    name = name,
-                    contents = InnerHtml(&contents, self.1 + 1),
-                    depth = self.1
-                )?,
-                Section { contents, kind: SectionKind::Paragraph, ..} | Section { name: None, contents, kind: SectionKind::Section } => write!(f, "<p>{}</p>", InnerHtml(&contents, self.1))?,
-                Text { contents } => write!(f, "{} ", contents)?,
-                Section { name: Some(name), contents, kind: SectionKind::Link } => write!(f, "<a href=\"{name}\">{contents}</a>", name = name, contents = InnerHtml(&contents, self.1))?,
-                Section { contents, kind: SectionKind::Code, ..} => write!(f, "<code>{contents}</code>", contents = InnerHtml(&contents, self.1))?,
-                Section { name: None, contents, kind: SectionKind::Link } => write!(f, "<a>{contents}</a>", contents = InnerHtml(&contents, self.1))?,
-                Section { name: Some(name), kind: SectionKind::Image, ..} => write!(f, "<img src=\"{name}\">", name = name)?,
-                Comment | Section { kind: SectionKind::Metadata, ..} | Section { kind: SectionKind::Image, ..} => ()
+                    "<li id=\"footnote-{number}\">{contents} <a href=\"#footnote-ref-{number}\" class=\"footnote-back\">&#8617;</a></li>",
+                    number = index + 1,
+                    contents = InnerHtml(html, contents, 1)
+                )?
+            }
+        }
+        f.write_str("</ol></section>")
+    }
+}
+/// A `table` section, where the header rows it starts with are its head
+struct Table<'a>(&'a Writer<'a>, &'a LeyLines<'a>, usize);
+impl<'a> Display for Table<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Table(html, contents, depth) = *self;
+        let table = table(contents);
+        let head = table.rows.iter().take_while(|row| row.header).count();
+        f.write_str("<table>")?;
+        for (index, row) in table.rows.iter().enumerate() {
+            if index == 0 && head > 0 {
+                f.write_str("<thead>")?
+            } else if index == head {
+                f.write_str("<tbody>")?
             }
+            f.write_str("<tr>")?;
+            let tag = if row.header { "th" } else { "td" };
+            let mut column = 0;
+            for (contents, span) in &row.cells {
+                write!(f, "<{}", tag)?;
+                if *span > 1 {
+                    write!(f, " colspan=\"{}\"", span)?
+                }
+                match table.alignments.get(column).copied().flatten() {
+                    Some(Alignment::Left) => f.write_str(" style=\"text-align: left\"")?,
+                    Some(Alignment::Center) => f.write_str(" style=\"text-align: center\"")?,
+                    Some(Alignment::Right) => f.write_str(" style=\"text-align: right\"")?,
+                    None => ()
+                }
+                write!(f, ">{}</{}>", InnerHtml(html, contents, depth), tag)?;
+                column += span
+            }
+            f.write_str("</tr>")?;
+            if index + 1 == head {
+                f.write_str("</thead>")?
+            }
+        }
+        if head < table.rows.len() {
+            f.write_str("</tbody>")?
+        }
+        f.write_str("</table>")
+    }
+}
+/// A table of contents, as nested lists of links to the headings
+struct Toc<'a>(&'a Option<ley::String<'a>>, Vec<&'a Heading<'a, 'a>>);
+impl<'a> Display for Toc<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Toc(title, headings) = self;
+        f.write_str("<nav class=\"toc\">")?;
+        if let Some(title) = title {
+            write!(f, "<p>{}</p>", EscapeText(title))?
+        }
+        let mut depth = 0;
+        for heading in headings {
+            if heading.depth > depth {
+                f.write_str(&"<ul><li>".repeat(heading.depth - depth))?
+            } else {
+                f.write_str("</li>")?;
+                f.write_str(&"</ul></li>".repeat(depth - heading.depth))?;
+                f.write_str("<li>")?
+            }
+            depth = heading.depth;
+            write!(f, "<a href=\"#{}\">{}</a>", EscapeAttribute(&heading.anchor), EscapeText(heading.name))?
+        }
+        f.write_str(&"</li></ul>".repeat(depth))?;
+        f.write_str("</nav>")
+    }
+}
+
+/// An attribute of an element that is only written if it has a value
+struct Attribute<'a>(&'a str, Option<&'a ley::String<'a>>);
+impl<'a> Display for Attribute<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(value) = self.1 {
+            write!(f, " {}=\"{}\"", self.0, EscapeAttribute(value))?
+        }
+        Ok(())
+    }
+}
+/// The class attribute marking the language of code
+struct Language<'a>(&'a Option<ley::String<'a>>);
+impl<'a> Display for Language<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(language) = self.0 {
+            write!(f, " class=\"language-{}\"", EscapeAttribute(language))?
+        }
+        Ok(())
+    }
+}
+/// The contents of a code section, exactly as written and highlighted if the language is known
+struct Code<'a>(&'a Option<ley::String<'a>>, &'a LeyLines<'a>);
+impl<'a> Display for Code<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let code = self.1.code();
+        match self.0.as_ref().and_then(|language| highlight(&language.to_string(), &code)) {
+            Some(pieces) => for piece in pieces {
+                match piece {
+                    (Some(class), code) => write!(f, "<span class=\"hl-{}\">{}</span>", class.name(), EscapeText(code))?,
+                    (None, code) => write!(f, "{}", EscapeText(code))?
+                }
+            },
+            None => write!(f, "{}", EscapeText(&code))?
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Maths as MathML, displayed on its own if the bool is set, with the TeX it was written in kept as an annotation
+struct Math<'a>(&'a Option<ley::String<'a>>, &'a LeyLines<'a>, bool);
+impl<'a> Display for Math<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Math(name, contents, display) = *self;
+        let tex = contents.plain().to_string();
+        f.write_str("<math")?;
+        if display {
+            f.write_str(" display=\"block\"")?
+        }
+        if let Some(name) = name {
+            write!(f, " alttext=\"{}\"", EscapeAttribute(name))?
+        }
+        write!(
+            f,
+            "><semantics><mrow>{}</mrow><annotation encoding=\"application/x-tex\">{}</annotation></semantics></math>",
+            mathml(tex.trim(), display),
+            EscapeText(tex.trim())
+        )
+    }
+}
+
+/// Whether the url is relative or has a scheme that cannot run scripts
+pub fn is_safe_url(url: &str) -> bool {
+    // Browsers ignore leading whitespace and control characters, so `\tjavascript:` is still a script
+    let url = url.trim_start_matches(|c: char| c.is_whitespace() || c.is_control());
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => {
+            let scheme = url[..end].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto")
+        }
+        _ => true
+    }
+}
+
+/// Displays the inner value with the characters special to html escaped, for use in text
+struct EscapeText<T>(T);
+impl<T: Display> Display for EscapeText<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(Escaper { f, quotes: false }, "{}", self.0)
+    }
+}
+/// Displays the inner value with the characters special to html escaped, for use in a quoted attribute value
+pub(crate) struct EscapeAttribute<T>(pub T);
+impl<T: Display> Display for EscapeAttribute<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(Escaper { f, quotes: true }, "{}", self.0)
+    }
+}
+struct Escaper<'a, 'b> {
+    f: &'a mut Formatter<'b>,
+    quotes: bool
+}
+impl<'a, 'b> Write for Escaper<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while let Some(index) = rest.find(|c| matches!(c, '&' | '<' | '>') || self.quotes && matches!(c, '"' | '\'')) {
+            self.f.write_str(&rest[..index])?;
+            self.f.write_str(match rest.as_bytes()[index] {
+                b'&' => "&amp;",
+                b'<' => "&lt;",
+                b'>' => "&gt;",
+                b'"' => "&quot;",
+                _ => "&#39;"
+            })?;
+            rest = &rest[index + 1..];
+        }
+        self.f.write_str(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The html that the ley file `source` is written as, without the page around it
+    fn html(source: &str) -> std::string::String {
+        let template = Template::parse("{{content}}", None).unwrap();
+        Html::from(Ley::new(source, None.into()).unwrap()).template(&template).to_string()
+    }
+
+    #[test]
+    fn safe_urls() {
+        for url in ["https://example.com", "HTTP://example.com", "mailto:a@example.com", "page.html", "../page.html#part", "/path?q=a:b", "#intro", ""] {
+            assert!(is_safe_url(url), "{:?}", url)
+        }
+        for url in ["javascript:alert(1)", "JavaScript:alert(1)", " javascript:alert(1)", "\t\njavascript:alert(1)", "\u{1}javascript:x", "java\tscript:x", "data:text/html,<script>", "vbscript:x"] {
+            assert!(!is_safe_url(url), "{:?}", url)
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(EscapeText("\"<a> & 'b'\"").to_string(), "\"&lt;a&gt; &amp; 'b'\"");
+        assert_eq!(EscapeAttribute("\"<a> & 'b'\"").to_string(), "&quot;&lt;a&gt; &amp; &#39;b&#39;&quot;");
+        // Escaping applies to everything displayed, however it is split between writes
+        assert_eq!(EscapeAttribute(format_args!("{}{}", "a&", "<b")).to_string(), "a&amp;&lt;b");
+    }
+
+    #[test]
+    fn highlighting() {
+        assert_eq!(html("!rust: code {\"if a < b {}\"}"), "<pre><code class=\"language-rust\"><span class=\"hl-keyword\">if</span> a &lt; b {}</code></pre>");
+        // Languages that aren't known are only escaped
+        assert_eq!(html("!cobol: code {\"if a < b\"}"), "<pre><code class=\"language-cobol\">if a &lt; b</code></pre>");
+    }
+
+    #[test]
+    fn footnotes() {
+        // A footnote within another is numbered after it, and each note links back to where it is referred to
+        assert_eq!(html("a!: footnote {one !: footnote {nested}} b!: footnote {two}"), concat!(
+            "a<sup class=\"footnote\" id=\"footnote-ref-1\"><a href=\"#footnote-1\">1</a></sup> b<sup class=\"footnote\" id=\"footnote-ref-3\"><a href=\"#footnote-3\">3</a></sup>",
+            "<section class=\"footnotes\"><ol>",
+            "<li id=\"footnote-1\">one <sup class=\"footnote\" id=\"footnote-ref-2\"><a href=\"#footnote-2\">2</a></sup> <a href=\"#footnote-ref-1\" class=\"footnote-back\">&#8617;</a></li>",
+            "<li id=\"footnote-2\">nested <a href=\"#footnote-ref-2\" class=\"footnote-back\">&#8617;</a></li>",
+            "<li id=\"footnote-3\">two <a href=\"#footnote-ref-3\" class=\"footnote-back\">&#8617;</a></li>",
+            "</ol></section>"
+        ));
+        assert!(!html("a").contains("footnotes"));
+    }
+
+    #[test]
+    fn lists() {
+        // A list nested in a list belongs to the item before it
+        assert_eq!(html("!: list {!: {Apples} one !: ordered {!start: meta {3} a !: {b}} two} !: ordered {x}"), "<ul><li>Apples</li><li>one<ol start=\"3\"><li>a</li><li>b</li></ol></li><li>two</li></ul> <ol><li>x</li></ol>");
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(html("!: table {!align: meta {left right} !: header {!: cell {Name} !: cell {Size}} !: row {!: cell {!colspan: meta {2} a | b}} !: row {!: cell {c} !: cell {*1*}}}"), concat!(
+            "<table><thead><tr><th style=\"text-align: left\">Name</th><th style=\"text-align: right\">Size</th></tr></thead>",
+            "<tbody><tr><td colspan=\"2\" style=\"text-align: left\">a | b</td></tr>",
+            "<tr><td style=\"text-align: left\">c</td><td style=\"text-align: right\"><em>1</em></td></tr></tbody></table>"
+        ));
+    }
+}
@@ -0,0 +1,549 @@
+//! Export and import of parsed ley files as json, so other tools can transform them between parsing and rendering.
+//!
+//! A ley file is an object with its metadata and lines:
+//!
+//! ```json
+//! {
+//!   "title": "Example Page",
+//!   "author": null,
+//!   "date": null,
+//!   "style": null,
+//!   "metadata": {"description": "An example"},
+//!   "lines": [...]
+//! }
+//! ```
+//!
+//! `metadata` holds any metadata other than the title, author, date and style, and may be left out when importing.
+//! A metadata value is a string, with dates in the iso format, a boolean or an array of values.
+//!
+//! Each line is an object whose `type` is one of
+//! - `"section"`, with the section's `kind` as written in a header (`"section"`, `"paragraph"`, `"meta"`, `"link"`, `"image"`,
+//!   `"code"`, `"inline"`, `"math"`, `"inline-math"` and so on), its `name` or `null`, and its `contents` as an array of lines
+//! - `"text"`, with its `text` exactly as written, whitespace included. Quotes are not kept, so on import whitespace within
+//!   quoted text is treated like any other whitespace between words
+//! - `"inline"`, with the `kind` of markup (`"emphasis"`, `"strong"`, `"underline"`, `"strikethrough"` or `"monospace"`)
+//!   and its `contents` as an array of lines
+//! - `"comment"`, with its `name` or `null`, the text of its `body` and its whole `source`, both exactly as written
+//!
+//! Every line also has a `span`, the `[start, end]` byte offsets of the line in the source. Renderers put a space between two lines
+//! when there was a gap between them in the source, so a span may be left out when importing to have the line spaced from those around it.
+
+use crate::{Ley, LeyLine, LeyLines, Format, Metadata, ley::{self, InlineKind, MetadataValue, ParseErrorKind, SectionKind, Span}};
+
+use std::{collections::HashMap, fmt::{self, Display, Formatter, Write}, ops::Deref};
+
+pub struct Json<'a> {
+    ley: Ley<'a>
+}
+impl<'a> Format<'a> for Json<'a> {
+    const EXTENSION: &'static str = "json";
+}
+impl<'a> From<Ley<'a>> for Json<'a> {
+    fn from(ley: Ley<'a>) -> Self {
+        Self {
+            ley
+        }
+    }
+}
+impl<'a> Display for Json<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut object = Vec::new();
+        for (name, metadata) in [("title", &self.title), ("author", &self.author), ("date", &self.date), ("style", &self.style)] {
+            object.push((name.to_string(), metadata.as_ref().map_or(Value::Null, export_metadata)))
+        }
+        let metadata = self.metadata.iter().map(|(name, value)| (name.clone(), export_metadata(value))).collect();
+        object.push(("metadata".to_string(), Value::Object(metadata)));
+        object.push(("lines".to_string(), export_lines(&self.lines)));
+        writeln!(f, "{:#}", Value::Object(object))
+    }
+}
+impl<'a> Deref for Json<'a> {
+    type Target = Ley<'a>;
+    fn deref(&self) -> &Self::Target {
+        &self.ley
+    }
+}
+
+fn export_metadata(value: &MetadataValue) -> Value {
+    match value {
+        MetadataValue::Text(text) => Value::String(text.clone()),
+        MetadataValue::Bool(value) => Value::Bool(*value),
+        MetadataValue::Date(date) => Value::String(date.to_string()),
+        MetadataValue::List(items) => Value::Array(items.iter().map(export_metadata).collect())
+    }
+}
+fn export_lines(lines: &LeyLines) -> Value {
+    Value::Array(lines.iter().map(export_line).collect())
+}
+fn export_line(ley_line: &LeyLine) -> Value {
+    let string = |name: &str, value: &str| (name.to_string(), Value::String(value.to_string()));
+    let mut object = match ley_line {
+        LeyLine::Section { name, kind, contents, .. } => vec![
+            string("type", "section"),
+            string("kind", kind.name()),
+            ("name".to_string(), name.as_ref().map_or(Value::Null, |name| Value::String(name.verbatim().to_string()))),
+            ("contents".to_string(), export_lines(contents))
+        ],
+        LeyLine::Text { contents, .. } => vec![
+            string("type", "text"),
+            string("text", &contents.verbatim().to_string())
+        ],
+        LeyLine::Inline { kind, contents, .. } => vec![
+            string("type", "inline"),
+            string("kind", kind.name()),
+            ("contents".to_string(), export_lines(contents))
+        ],
+        LeyLine::Comment { name, body, source, .. } => vec![
+            string("type", "comment"),
+            ("name".to_string(), name.as_ref().map_or(Value::Null, |name| Value::String(name.verbatim().to_string()))),
+            string("body", body),
+            string("source", source)
+        ]
+    };
+    let span = ley_line.span();
+    object.push(("span".to_string(), Value::Array(vec![Value::Number(span.start as f64), Value::Number(span.end as f64)])));
+    Value::Object(object)
+}
+
+/// Read a ley file back from its json, borrowing its text from `value`
+pub fn import(value: &Value) -> Result<Ley<'_>, &'static str> {
+    let metadata = |name| value.get(name).map_or(Ok(None), import_metadata).map(Metadata);
+    Ok(Ley {
+        title: metadata("title")?,
+        author: metadata("author")?,
+        date: metadata("date")?,
+        style: metadata("style")?,
+        metadata: match value.get("metadata") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Object(object)) => {
+                let mut metadata = Vec::new();
+                for (name, value) in object {
+                    if let Some(value) = import_metadata(value)? {
+                        ley::set_metadata(&mut metadata, name.clone(), value)
+                    }
+                }
+                metadata
+            },
+            Some(_) => return Err("Metadata must be an object")
+        },
+        lines: import_lines(value.get("lines").ok_or("A ley file must have lines")?, &mut 0)?,
+        anchors: HashMap::new()
+    })
+}
+/// Import a metadata value, which is missing if it is null
+fn import_metadata(value: &Value) -> Result<Option<MetadataValue>, &'static str> {
+    match value {
+        Value::Null => Ok(None),
+        Value::Bool(value) => Ok(Some(MetadataValue::Bool(*value))),
+        Value::String(text) => MetadataValue::from_text(text.clone()).map(Some).map_err(|_| "Invalid date in metadata"),
+        Value::Array(items) => items.iter()
+            .map(|item| import_metadata(item)?.ok_or("Metadata lists can't contain null"))
+            .collect::<Result<_, _>>()
+            .map(|items| Some(MetadataValue::List(items))),
+        _ => Err("Metadata must be a string, boolean, array or null")
+    }
+}
+/// Import an array of lines, with `pos` the end of the last span, from which missing spans are made up
+fn import_lines<'a>(value: &'a Value, pos: &mut usize) -> Result<LeyLines<'a>, &'static str> {
+    match value {
+        Value::Array(lines) => Ok(LeyLines(lines.iter().map(|line| import_line(line, pos)).collect::<Result<_, _>>()?)),
+        _ => Err("Lines must be an array")
+    }
+}
+fn import_line<'a>(value: &'a Value, pos: &mut usize) -> Result<LeyLine<'a>, &'static str> {
+    let span = match value.get("span") {
+        // Leaving a gap after the last span spaces the line from the one before it
+        None => Span::new(pos.saturating_add(1), pos.saturating_add(1)),
+        Some(Value::Array(span)) => match span.as_slice() {
+            [Value::Number(start), Value::Number(end)] if start > end => return Err("A span must not end before it starts"),
+            [Value::Number(start), Value::Number(end)] => Span::new(*start as usize, *end as usize),
+            _ => return Err("A span must be an array of its start and end")
+        },
+        Some(_) => return Err("A span must be an array of its start and end")
+    };
+    // Offsets far past the end of any source are kept from overflowing
+    *pos = span.end.max(pos.saturating_add(1));
+    let contents = value.get("contents").ok_or("Sections and inline markup must have contents");
+    let name = match value.get("name") {
+        None | Some(Value::Null) => None,
+        Some(Value::String(name)) => Some(ley::String::split(name)),
+        Some(_) => return Err("The name of a section must be a string or null")
+    };
+    match value.get("type").and_then(Value::as_str) {
+        Some("section") => {
+            let kind = value.get("kind").and_then(Value::as_str).and_then(|kind| SectionKind::new(kind).ok()).ok_or("Unknown kind of section")?;
+            let contents = import_lines(contents?, pos)?;
+            // Sections are checked as they would be when parsed, as renderers rely on it
+            let mut errors = Vec::new();
+            if !kind.check(&name, &contents, span, &mut errors) {
+                return Err(invalid(&errors[0].kind))
+            }
+            Ok(LeyLine::Section { name, kind, contents, span })
+        },
+        Some("text") => Ok(LeyLine::Text {
+            contents: ley::String::split(value.get("text").and_then(Value::as_str).ok_or("Text must have a string of text")?),
+            span
+        }),
+        Some("inline") => {
+            let kind = value.get("kind").and_then(Value::as_str).and_then(InlineKind::from_name).ok_or("Unknown kind of inline markup")?;
+            let contents = import_lines(contents?, pos)?;
+            // As when parsed, markup always has something to mark up
+            if contents.is_empty() {
+                return Err("Inline markup must not be empty")
+            }
+            Ok(LeyLine::Inline { kind, contents, span })
+        },
+        Some("comment") => Ok(LeyLine::Comment {
+            name,
+            body: value.get("body").and_then(Value::as_str).unwrap_or_default(),
+            source: value.get("source").and_then(Value::as_str).unwrap_or_default(),
+            span
+        }),
+        _ => Err("Unknown type of line")
+    }
+}
+
+/// Why a section is invalid, given the first error found in it
+fn invalid(error: &ParseErrorKind) -> &'static str {
+    match error {
+        ParseErrorKind::MissingImageSource => "Images require a source, given as the name of the section",
+        ParseErrorKind::MissingReference => "References require the section they link to, given as the name of the section",
+        ParseErrorKind::InvalidReference(_) => "References to other files must name a ley file in the same directory",
+        ParseErrorKind::UnknownAttribute(_) => "Unknown attribute of a section",
+        ParseErrorKind::ExpectedString => "Attributes of a section must be a string",
+        ParseErrorKind::ExpectedNumber => "Expected a whole number greater than 0 for an attribute of a section",
+        ParseErrorKind::InvalidAlignment(_) => "Invalid alignment of a table, expected `left`, `center` or `right`",
+        ParseErrorKind::ExpectedRow => "Tables must be made of `row` and `header` sections",
+        ParseErrorKind::ExpectedCell => "Rows of a table must be made of `cell` sections",
+        _ => "Invalid section"
+    }
+}
+
+/// A json value, with the members of objects kept in order
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(std::string::String),
+    Array(Vec<Value>),
+    Object(Vec<(std::string::String, Value)>)
+}
+impl Value {
+    /// Parse a json document
+    pub fn parse(source: &str) -> Result<Self, &'static str> {
+        let mut parser = Parser { source, pos: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.pos < source.len() {
+            return Err("Unexpected characters after the json value")
+        }
+        Ok(value)
+    }
+    /// The member `name` of an object
+    pub fn get(&self, name: &str) -> Option<&Self> {
+        match self {
+            Self::Object(members) => members.iter().find(|(member, _)| member == name).map(|(_, value)| value),
+            _ => None
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None
+        }
+    }
+    fn write(&self, f: &mut Formatter, indent: usize) -> fmt::Result {
+        // Arrays of only numbers, strings and the like are short enough to keep on one line
+        let pretty = f.alternate() && !matches!(self, Self::Array(values) if values.iter().all(|value| !matches!(value, Self::Array(_) | Self::Object(_))));
+        let newline = |f: &mut Formatter, indent: usize| if pretty {
+            write!(f, "\n{:1$}", "", indent * 2)
+        } else {
+            Ok(())
+        };
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Number(value) if value.is_finite() => write!(f, "{}", value),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(value) => write!(f, "{}", Quoted(value)),
+            Self::Array(values) if values.is_empty() => f.write_str("[]"),
+            Self::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?
+                    }
+                    newline(f, indent + 1)?;
+                    value.write(f, indent + 1)?
+                }
+                newline(f, indent)?;
+                f.write_str("]")
+            },
+            Self::Object(members) if members.is_empty() => f.write_str("{}"),
+            Self::Object(members) => {
+                f.write_str("{")?;
+                for (index, (name, value)) in members.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?
+                    }
+                    newline(f, indent + 1)?;
+                    write!(f, "{}:{}", Quoted(name), if pretty { " " } else { "" })?;
+                    value.write(f, indent + 1)?
+                }
+                newline(f, indent)?;
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl Display for Value {
+    /// The alternate form, `{:#}`, is indented over multiple lines
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// A json string, with the characters that can't appear within quotes escaped
+struct Quoted<'a>(&'a str);
+impl<'a> Display for Quoted<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// The deepest arrays and objects may nest, so that malicious input can't overflow the stack
+const MAX_DEPTH: usize = 256;
+
+struct Parser<'a> {
+    source: &'a str,
+    pos: usize,
+    depth: usize
+}
+impl<'a> Parser<'a> {
+    fn value(&mut self) -> Result<Value, &'static str> {
+        self.whitespace();
+        let rest = &self.source[self.pos..];
+        match rest.bytes().next() {
+            Some(b'n') => self.keyword("null", Value::Null),
+            Some(b't') => self.keyword("true", Value::Bool(true)),
+            Some(b'f') => self.keyword("false", Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => self.nested(b']', |parser| {
+                let mut values = Vec::new();
+                parser.list(b']', |parser| {
+                    values.push(parser.value()?);
+                    Ok(())
+                })?;
+                Ok(Value::Array(values))
+            }),
+            Some(b'{') => self.nested(b'}', |parser| {
+                let mut members = Vec::new();
+                parser.list(b'}', |parser| {
+                    parser.whitespace();
+                    if !parser.source[parser.pos..].starts_with('"') {
+                        return Err("Expected the name of an object member")
+                    }
+                    let name = parser.string()?;
+                    parser.whitespace();
+                    if !parser.eat(b':') {
+                        return Err("Expected `:` after the name of an object member")
+                    }
+                    members.push((name, parser.value()?));
+                    Ok(())
+                })?;
+                Ok(Value::Object(members))
+            }),
+            Some(b'-' | b'0'..=b'9') => {
+                let length = rest.find(|c: char| !(c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))).unwrap_or(rest.len());
+                self.pos += length;
+                rest[..length].parse().map(Value::Number).map_err(|_| "Invalid number")
+            },
+            Some(_) => Err("Unexpected character in json"),
+            None => Err("Unexpected end of json")
+        }
+    }
+    /// Parse an array or object opened by the current character, with `parse` reading its contents
+    fn nested(&mut self, close: u8, parse: impl FnOnce(&mut Self) -> Result<Value, &'static str>) -> Result<Value, &'static str> {
+        if self.depth == MAX_DEPTH {
+            return Err("Json is nested too deeply")
+        }
+        self.depth += 1;
+        self.pos += 1;
+        let value = parse(self)?;
+        self.whitespace();
+        if !self.eat(close) {
+            return Err("Expected `,` or the end of an array or object")
+        }
+        self.depth -= 1;
+        Ok(value)
+    }
+    /// Parse the comma separated items of an array or object with `item`, stopping before `close`
+    fn list(&mut self, close: u8, mut item: impl FnMut(&mut Self) -> Result<(), &'static str>) -> Result<(), &'static str> {
+        self.whitespace();
+        if self.source.as_bytes().get(self.pos) == Some(&close) {
+            return Ok(())
+        }
+        loop {
+            item(self)?;
+            self.whitespace();
+            if !self.eat(b',') {
+                return Ok(())
+            }
+        }
+    }
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, &'static str> {
+        if self.source[self.pos..].starts_with(keyword) {
+            self.pos += keyword.len();
+            Ok(value)
+        } else {
+            Err("Unexpected word in json")
+        }
+    }
+    fn string(&mut self) -> Result<std::string::String, &'static str> {
+        let mut string = std::string::String::new();
+        let mut chars = self.source[self.pos + 1..].char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += 1 + index + 1;
+                    return Ok(string)
+                },
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => {
+                        let mut code = hex(&mut chars)?;
+                        // Characters outside the basic multilingual plane are written as a surrogate pair
+                        if (0xD800..0xDC00).contains(&code) {
+                            if chars.next().map(|(_, c)| c) != Some('\\') || chars.next().map(|(_, c)| c) != Some('u') {
+                                return Err("Unpaired surrogate in json string")
+                            }
+                            let low = hex(&mut chars)?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err("Unpaired surrogate in json string")
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+                        }
+                        string.push(char::from_u32(code).ok_or("Invalid unicode escape in json string")?)
+                    },
+                    _ => return Err("Invalid escape in json string")
+                },
+                c if c.is_control() => return Err("Unescaped control character in json string"),
+                c => string.push(c)
+            }
+        }
+        Err("Unclosed json string")
+    }
+    fn eat(&mut self, c: u8) -> bool {
+        if self.source.as_bytes().get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn whitespace(&mut self) {
+        let rest = &self.source[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len()
+    }
+}
+
+/// Read the four hexadecimal digits of a unicode escape
+fn hex(chars: &mut std::str::CharIndices) -> Result<u32, &'static str> {
+    let mut code = 0;
+    for _ in 0..4 {
+        let digit = chars.next().and_then(|(_, c)| c.to_digit(16)).ok_or("Invalid unicode escape in json string")?;
+        code = code * 16 + digit
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The json that the ley file `source` is exported as
+    fn export(source: &str) -> std::string::String {
+        Json::from(Ley::new(source, None.into()).unwrap()).to_string()
+    }
+    /// Import `json`, giving the error if it isn't a valid ley file
+    fn check(json: &str) -> Result<(), &'static str> {
+        import(&Value::parse(json).unwrap()).map(|_| ())
+    }
+    /// Export and import the ley file `source`, checking that it is exported the same again
+    fn round_trip(source: &str) {
+        let json = export(source);
+        let value = Value::parse(&json).unwrap();
+        assert_eq!(Json::from(import(&value).unwrap()).to_string(), json)
+    }
+
+    #[test]
+    fn examples() {
+        round_trip(include_str!("../tests/example.ley"));
+        round_trip(include_str!("../tests/simple.ley"));
+    }
+
+    #[test]
+    fn metadata() {
+        round_trip("!description: meta {A file} !tags: meta {!: meta {one} !: meta {two}} !draft: meta {true} !weight: meta {2}");
+    }
+
+    #[test]
+    fn references() {
+        let reference = export("!\"guide#Setup\": ref {}");
+        assert_eq!(check(&reference), Ok(()));
+        assert_eq!(check(&reference.replace("guide#", "../guide#")), Err("References to other files must name a ley file in the same directory"));
+        assert_eq!(check(&reference.replace("\"guide#Setup\"", "null")), Err("References require the section they link to, given as the name of the section"));
+    }
+
+    #[test]
+    fn lists() {
+        let ordered = export("!: ordered {!start: meta {3} a}");
+        assert_eq!(check(&ordered), Ok(()));
+        assert_eq!(check(&ordered.replace("\"text\": \"3\"", "\"text\": \"0\"")), Err("Expected a whole number greater than 0 for an attribute of a section"));
+    }
+
+    #[test]
+    fn tables() {
+        let table = export("!: table {!align: meta {left right} !: row {!: cell {!colspan: meta {2} a} !: cell {b}}}");
+        assert_eq!(check(&table), Ok(()));
+        assert_eq!(check(&table.replace("\"text\": \"2\"", "\"text\": \"0\"")), Err("Expected a whole number greater than 0 for an attribute of a section"));
+        assert_eq!(check(&table.replace("left right", "left middle")), Err("Invalid alignment of a table, expected `left`, `center` or `right`"));
+        assert_eq!(check(&table.replace("\"colspan\"", "\"rowspan\"")), Err("Unknown attribute of a section"));
+        assert_eq!(check(&table.replace("\"kind\": \"cell\"", "\"kind\": \"section\"")), Err("Rows of a table must be made of `cell` sections"));
+    }
+
+    #[test]
+    fn markup() {
+        let markup = export("*a*");
+        assert_eq!(check(&markup), Ok(()));
+        assert_eq!(check(&markup.replace("\"contents\": [", "\"contents\": [], \"was\": [")), Err("Inline markup must not be empty"));
+    }
+
+    #[test]
+    fn spans() {
+        // Spans may be as large as a number can be, but not backwards
+        assert_eq!(check(r#"{"lines": [{"type": "text", "text": "a", "span": [0, 1e300]}, {"type": "text", "text": "b"}]}"#), Ok(()));
+        assert_eq!(check(r#"{"lines": [{"type": "text", "text": "a", "span": [2, 1]}]}"#), Err("A span must not end before it starts"));
+    }
+}
@@ -0,0 +1,446 @@
+use crate::{Ley, LeyLine, LeyLines, ley::{self, MetadataValue}, fmt::{Alignment, Blocks, Outline, inline, is_shown, items, list_start, table, toc_depth}, Format};
+
+use std::{fmt::{self, Display, Formatter, Write}, ops::Deref};
+
+pub struct Latex<'a> {
+    ley: Ley<'a>
+}
+impl<'a> Format<'a> for Latex<'a> {
+    const EXTENSION: &'static str = "tex";
+}
+impl<'a> From<Ley<'a>> for Latex<'a> {
+    fn from(ley: Ley<'a>) -> Self {
+        Self {
+            ley
+        }
+    }
+}
+impl<'a> Display for Latex<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(PREAMBLE)?;
+        // `\maketitle` requires a title and would otherwise fill in a missing date with today's, so each is given even when empty
+        let title = self.title.is_some() || self.author.is_some() || self.date.is_some();
+        if title {
+            writeln!(f, "\\title{{{}}}", Escape(self.title.as_ref().map_or(std::string::String::new(), |title| self.show(title))))?;
+            // Several authors are set side by side
+            let authors: Vec<_> = match self.author.as_ref() {
+                Some(MetadataValue::List(authors)) => authors.iter().map(|author| Escape(self.show(author)).to_string()).collect(),
+                Some(author) => vec![Escape(self.show(author)).to_string()],
+                None => Vec::new()
+            };
+            writeln!(f, "\\author{{{}}}", authors.join(" \\and "))?;
+            writeln!(f, "\\date{{{}}}", Escape(self.date.as_ref().map_or(std::string::String::new(), |date| self.show(date))))?;
+        }
+        f.write_str("\\begin{document}\n")?;
+        if title {
+            f.write_str("\\maketitle\n")?
+        }
+        let outline = Outline::new(&self.ley);
+        let mut writer = Writer {
+            blocks: Blocks::new(f),
+            outline: &outline,
+            paragraph: false
+        };
+        writer.write(&self.ley.lines, 1)?;
+        writer.blocks.finish()?;
+        f.write_str("\\end{document}\n")
+    }
+}
+impl<'a> Deref for Latex<'a> {
+    type Target = Ley<'a>;
+    fn deref(&self) -> &Self::Target {
+        &self.ley
+    }
+}
+
+const PREAMBLE: &str = "\\documentclass{article}
+\\usepackage[T1]{fontenc}
+\\usepackage[utf8]{inputenc}
+\\usepackage{graphicx}
+\\usepackage{amsmath}
+\\usepackage{amssymb}
+\\usepackage{listings}
+\\usepackage[normalem]{ulem}
+\\usepackage{hyperref}
+\\lstset{basicstyle=\\ttfamily, breaklines=true}
+";
+
+/// The sectioning commands for each depth, with anything deeper using the last
+const SECTIONS: [&str; 5] = ["section", "subsection", "subsubsection", "paragraph", "subparagraph"];
+
+/// Writes ley lines as the body of a latex document, where a blank line ends a paragraph
+struct Writer<'a, 'b, 'c> {
+    blocks: Blocks<'a, 'b>,
+    /// The outline of the whole document, which sections are labelled and referred to within
+    outline: &'a Outline<'a, 'c>,
+    /// Whether a paragraph is open that inline lines may continue
+    paragraph: bool
+}
+impl<'a, 'b, 'c> Writer<'a, 'b, 'c> {
+    fn write(&mut self, lines: &LeyLines, depth: usize) -> fmt::Result {
+        use LeyLine::*;
+        use ley::SectionKind;
+        for (ley_line, spaced) in lines.spaced() {
+            match ley_line {
+                Section { name: Some(name), contents, kind: SectionKind::Section, .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "\\{}{{{}}}", SECTIONS[(depth - 1).min(SECTIONS.len() - 1)], Escape(name))?;
+                    if let Some(heading) = self.outline.heading(ley_line) {
+                        write!(self.blocks.f, "\\label{{{}}}", heading.anchor)?
+                    }
+                    self.write(contents, depth + 1)?;
+                    self.paragraph = false
+                },
+                Section { contents, kind: SectionKind::Paragraph, ..} | Section { name: None, contents, kind: SectionKind::Section, .. } => {
+                    self.paragraph = false;
+                    self.write(contents, depth)?;
+                    self.paragraph = false
+                },
+                Section { name, contents, kind: SectionKind::Code, .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "{}", Listing(name, contents))?
+                },
+                Section { contents, kind: SectionKind::Math, .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "\\[\n{}\n\\]", contents.code().trim())?
+                },
+                Section { name: Some(_), contents, kind: SectionKind::Image, .. } if contents.attribute("caption").is_some() => {
+                    self.start()?;
+                    write!(
+                        self.blocks.f,
+                        "\\begin{{figure}}[h]\n\\centering\n{}\n\\caption{{{}}}\n\\end{{figure}}",
+                        Line(self.outline, ley_line),
+                        Escape(contents.attribute("caption").unwrap())
+                    )?
+                },
+                Section { name, contents, kind: SectionKind::Toc, .. } => {
+                    self.start()?;
+                    if let Some(name) = name {
+                        writeln!(self.blocks.f, "\\renewcommand{{\\contentsname}}{{{}}}", Escape(name))?
+                    }
+                    write!(self.blocks.f, "\\setcounter{{tocdepth}}{{{}}}\n\\tableofcontents", toc_depth(contents).min(SECTIONS.len()))?
+                },
+                Section { contents, kind: kind @ (SectionKind::List | SectionKind::Ordered), .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "{}", List(self.outline, *kind, contents, 0))?
+                },
+                Section { contents, kind: SectionKind::Table, .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "{}", Table(self.outline, contents))?
+                },
+                Comment { .. } | Section { kind: SectionKind::Metadata, .. } => {
+                    self.blocks.space |= spaced;
+                    continue
+                },
+                _ => {
+                    if !self.paragraph {
+                        self.start()?;
+                        self.paragraph = true
+                    } else if self.blocks.space {
+                        self.blocks.f.write_str(" ")?
+                    }
+                    write!(self.blocks.f, "{}", Line(self.outline, ley_line))?
+                }
+            }
+            self.blocks.space = spaced
+        }
+        Ok(())
+    }
+    /// Begin a sectioning command, environment or new paragraph, ending the paragraph before it
+    fn start(&mut self) -> fmt::Result {
+        self.paragraph = false;
+        self.blocks.start()
+    }
+}
+
+/// A code section as a listing, naming the language if listings knows it
+struct Listing<'a>(&'a Option<ley::String<'a>>, &'a LeyLines<'a>);
+impl<'a> Display for Listing<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Listings stops with an error on languages it doesn't define, so only those it does are passed on
+        let language = self.0.as_ref().and_then(|name| match name.to_string().to_ascii_lowercase().as_str() {
+            "c" | "h" => Some("C"),
+            "python" | "py" => Some("Python"),
+            "shell" | "sh" | "bash" | "zsh" => Some("bash"),
+            "java" => Some("Java"),
+            "html" => Some("HTML"),
+            _ => None
+        });
+        let code = self.1.code();
+        // Code can't contain the end of the listing, so its backslash is set by latex outside the code,
+        // between escape characters that the code doesn't use
+        let escape = if code.contains(END_LISTING) { ESCAPES.chars().find(|&c| !code.contains(c)) } else { None };
+        let mut options = Vec::new();
+        if let Some(language) = language {
+            options.push(format!("language={}", language))
+        }
+        if let Some(escape) = escape {
+            options.push(format!("escapechar={}", escape))
+        }
+        f.write_str("\\begin{lstlisting}")?;
+        if !options.is_empty() {
+            write!(f, "[{}]", options.join(", "))?
+        }
+        let code = match escape {
+            Some(escape) => code.replace(END_LISTING, &format!("{0}\\textbackslash{0}end{{lstlisting}}", escape)),
+            // With every escape character used, a space keeps the end from being read as such
+            None => code.replace(END_LISTING, "\\end {lstlisting}")
+        };
+        write!(f, "\n{}\n{}", code, END_LISTING)
+    }
+}
+/// What ends a listing
+const END_LISTING: &str = "\\end{lstlisting}";
+/// The characters that may escape from a listing to latex, in the order they are tried
+const ESCAPES: &str = "|@!?*+/;:";
+
+/// The counters of nested `enumerate` environments, from the outermost
+const COUNTERS: [&str; 4] = ["enumi", "enumii", "enumiii", "enumiv"];
+
+/// A `list` or `ordered` section within `ordered` numbered lists, with the lists nested in an item kept within it
+struct List<'a>(&'a Outline<'a, 'a>, ley::SectionKind, &'a LeyLines<'a>, usize);
+impl<'a> Display for List<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let List(outline, kind, contents, ordered) = *self;
+        let (environment, nested) = if kind == ley::SectionKind::Ordered { ("enumerate", ordered + 1) } else { ("itemize", ordered) };
+        write!(f, "\\begin{{{}}}", environment)?;
+        let start = list_start(contents);
+        if kind == ley::SectionKind::Ordered && start != 1 {
+            if let Some(counter) = COUNTERS.get(ordered) {
+                // The counter is stepped by each item, so it starts one below the first number
+                write!(f, "\n\\setcounter{{{}}}{{{}}}", counter, start.saturating_sub(1))?
+            }
+        }
+        for item in items(contents) {
+            f.write_str("\n\\item")?;
+            for (position, (ley_line, spaced)) in item.lines.iter().enumerate() {
+                if position == 0 {
+                    f.write_str(" ")?
+                }
+                write!(f, "{}", Line(outline, ley_line))?;
+                if *spaced && position + 1 < item.lines.len() {
+                    f.write_str(" ")?
+                }
+            }
+            for list in item.lists {
+                if let LeyLine::Section { kind, contents, .. } = list {
+                    write!(f, "\n{}", List(outline, *kind, contents, nested))?
+                }
+            }
+        }
+        write!(f, "\n\\end{{{}}}", environment)
+    }
+}
+
+/// A `table` section as a `tabular`, with a rule under its header rows
+struct Table<'a>(&'a Outline<'a, 'a>, &'a LeyLines<'a>);
+impl<'a> Display for Table<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Table(outline, contents) = *self;
+        let table = table(contents);
+        let column = |alignment: Option<Alignment>| match alignment {
+            Some(Alignment::Center) => 'c',
+            Some(Alignment::Right) => 'r',
+            Some(Alignment::Left) | None => 'l'
+        };
+        write!(f, "\\begin{{tabular}}{{{}}}", table.alignments.iter().map(|alignment| column(*alignment)).collect::<std::string::String>())?;
+        for (index, row) in table.rows.iter().enumerate() {
+            f.write_str("\n")?;
+            let mut position = 0;
+            for (cell, (contents, span)) in row.cells.iter().enumerate() {
+                if cell > 0 {
+                    f.write_str(" & ")?
+                }
+                let text = if row.header { format!("\\textbf{{{}}}", InlineLatex(outline, contents)) } else { InlineLatex(outline, contents).to_string() };
+                if *span > 1 {
+                    let alignment = table.alignments.get(position).copied().flatten();
+                    write!(f, "\\multicolumn{{{}}}{{{}}}{{{}}}", span, column(alignment), text)?
+                } else {
+                    f.write_str(&text)?
+                }
+                position += span
+            }
+            f.write_str(" \\\\")?;
+            if row.header && table.rows.get(index + 1).is_some_and(|next| !next.header) {
+                f.write_str("\n\\hline")?
+            }
+        }
+        f.write_str("\n\\end{tabular}")
+    }
+}
+
+/// Ley lines within a paragraph, which are all written inline, along with the outline of the whole document that references are looked up in
+struct InlineLatex<'a>(&'a Outline<'a, 'a>, &'a LeyLines<'a>);
+impl<'a> Display for InlineLatex<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let InlineLatex(outline, lines) = *self;
+        for (ley_line, space) in inline(lines, is_shown) {
+            if space {
+                f.write_str(" ")?
+            }
+            write!(f, "{}", Line(outline, ley_line))?
+        }
+        Ok(())
+    }
+}
+
+/// A single ley line written inline, along with the outline of the whole document
+struct Line<'a>(&'a Outline<'a, 'a>, &'a LeyLine<'a>);
+impl<'a> Display for Line<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use LeyLine::*;
+        use ley::SectionKind;
+        let Line(outline, ley_line) = *self;
+        match ley_line {
+            Text { contents, .. } => write!(f, "{}", Escape(contents)),
+            Inline { kind, contents, .. } => {
+                use ley::InlineKind;
+                let command = match kind {
+                    InlineKind::Emphasis => "emph",
+                    InlineKind::Strong => "textbf",
+                    InlineKind::Underline => "uline",
+                    InlineKind::Strikethrough => "sout",
+                    InlineKind::Monospace => "texttt"
+                };
+                write!(f, "\\{}{{{}}}", command, InlineLatex(outline, contents))
+            },
+            Section { name: Some(name), contents, kind: SectionKind::Link, .. } => write!(f, "\\href{{{}}}{{{}}}", Url(name), InlineLatex(outline, contents)),
+            Section { name: Some(name), contents, kind: SectionKind::Image, .. } => {
+                let options: Vec<_> = ["width", "height"].iter()
+                    .filter_map(|option| contents.attribute(option).map(|value| format!("{}={}", option, Dimension(value))))
+                    .collect();
+                f.write_str("\\includegraphics")?;
+                if !options.is_empty() {
+                    write!(f, "[{}]", options.join(","))?
+                }
+                write!(f, "{{{}}}", Url(name))
+            },
+            Section { contents, kind: SectionKind::Code | SectionKind::InlineCode, .. } => write!(f, "\\texttt{{{}}}", Escape(contents.code())),
+            // Maths is already latex, so it is written as it is
+            Section { contents, kind: SectionKind::Math | SectionKind::InlineMath, .. } => write!(f, "\\({}\\)", contents.code().trim()),
+            Section { contents, kind: SectionKind::Footnote, .. } => write!(f, "\\footnote{{{}}}", InlineLatex(outline, contents)),
+            Section { name: Some(target), contents, kind: SectionKind::Ref, .. } => {
+                let reference = outline.reference(target);
+                let text = if contents.is_empty() {
+                    Escape(&reference.name).to_string()
+                } else {
+                    InlineLatex(outline, contents).to_string()
+                };
+                // The document is a single file, so there is nowhere to link a section of another file to
+                match reference.file {
+                    Some(_) => f.write_str(&text),
+                    None => write!(f, "\\hyperref[{}]{{{}}}", reference.anchor, text)
+                }
+            },
+            Section { kind: SectionKind::Image | SectionKind::Metadata | SectionKind::Toc, .. } | Comment { .. } => Ok(()),
+            Section { contents, .. } => write!(f, "{}", InlineLatex(outline, contents))
+        }
+    }
+}
+
+/// The size of an image, where a plain number is taken to be in pixels as in html
+struct Dimension<'a>(&'a ley::String<'a>);
+impl<'a> Display for Dimension<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let value = self.0.to_string();
+        if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            write!(f, "{}px", value)
+        } else {
+            // Anything that could end the option list or its group is dropped
+            f.write_str(&value.replace([',', ']', '{', '}'], ""))
+        }
+    }
+}
+/// A url or path, with the characters that latex would interpret escaped
+struct Url<'a>(&'a ley::String<'a>);
+impl<'a> Display for Url<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for c in self.0.to_string().chars() {
+            match c {
+                '#' => f.write_str("\\#")?,
+                '%' => f.write_str("\\%")?,
+                // Braces and backslashes cannot be escaped within a url, so are percent encoded instead
+                '\\' => f.write_str("%5C")?,
+                '{' => f.write_str("%7B")?,
+                '}' => f.write_str("%7D")?,
+                c => f.write_char(c)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Displays the inner value with the characters special to latex escaped, for use in text
+struct Escape<T>(T);
+impl<T: Display> Display for Escape<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(Escaper(f), "{}", self.0)
+    }
+}
+struct Escaper<'a, 'b>(&'a mut Formatter<'b>);
+impl<'a, 'b> Write for Escaper<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while let Some(index) = rest.find(['\\', '{', '}', '$', '&', '#', '%', '_', '~', '^', '<', '>', '|']) {
+            self.0.write_str(&rest[..index])?;
+            self.0.write_str(match rest.as_bytes()[index] {
+                b'\\' => "\\textbackslash{}",
+                b'{' => "\\{",
+                b'}' => "\\}",
+                b'$' => "\\$",
+                b'&' => "\\&",
+                b'#' => "\\#",
+                b'%' => "\\%",
+                b'_' => "\\_",
+                b'~' => "\\textasciitilde{}",
+                b'^' => "\\textasciicircum{}",
+                b'<' => "\\textless{}",
+                b'>' => "\\textgreater{}",
+                _ => "\\textbar{}"
+            })?;
+            rest = &rest[index + 1..];
+        }
+        self.0.write_str(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The body of the latex document that the ley file `source` is written as
+    fn latex(source: &str) -> std::string::String {
+        let latex = Latex::from(Ley::new(source, None.into()).unwrap()).to_string();
+        latex[latex.find("\\begin{document}").unwrap()..].to_string()
+    }
+
+    #[test]
+    fn listings() {
+        assert!(latex("!c: code {\"x = 1;\"}").contains("\\begin{lstlisting}[language=C]\nx = 1;\n\\end{lstlisting}"));
+        // The end of a listing within its code is kept from ending it early
+        let latex = latex("!c: code {\"a | b \\end{lstlisting} \\input{secret}\"}");
+        assert!(latex.contains("\\begin{lstlisting}[language=C, escapechar=@]\na | b @\\textbackslash@end{lstlisting} \\input{secret}\n\\end{lstlisting}"));
+        assert_eq!(latex.matches("\\end{lstlisting}").count(), 1);
+    }
+
+    #[test]
+    fn footnotes() {
+        assert!(latex("a!: footnote {one !: footnote {nested}} b!: footnote {two}").contains("a\\footnote{one \\footnote{nested}} b\\footnote{two}"));
+    }
+
+    #[test]
+    fn lists() {
+        assert!(latex("!: list {!: {Apples} one !: ordered {!start: meta {3} a !: {b}} two} !: ordered {x}").contains(concat!(
+            "\\begin{itemize}\n\\item Apples\n\\item one\n\\begin{enumerate}\n\\setcounter{enumi}{2}\n\\item a\n\\item b\n\\end{enumerate}\n",
+            "\\item two\n\\end{itemize}\n\n\\begin{enumerate}\n\\item x\n\\end{enumerate}"
+        )));
+    }
+
+    #[test]
+    fn tables() {
+        assert!(latex("!: table {!align: meta {left right} !: header {!: cell {Name} !: cell {Size}} !: row {!: cell {!colspan: meta {2} a | b}} !: row {!: cell {c} !: cell {*1*}}}").contains(concat!(
+            "\\begin{tabular}{lr}\n\\textbf{Name} & \\textbf{Size} \\\\\n\\hline\n",
+            "\\multicolumn{2}{l}{a \\textbar{} b} \\\\\nc & \\emph{1} \\\\\n\\end{tabular}"
+        )));
+    }
+}
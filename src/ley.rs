@@ -1,4 +1,6 @@
-use std::{cmp::PartialEq, fmt::{self, Debug, Display, Formatter}, ops::{Deref,DerefMut}};
+use crate::fmt::inline;
+
+use std::{borrow::Cow, cmp::PartialEq, collections::HashMap, fmt::{self, Debug, Display, Formatter}, ops::{Deref,DerefMut}};
 
 /// A parsed ley file
 pub struct Ley<'a> {
@@ -6,72 +8,121 @@ pub struct Ley<'a> {
     pub title: Metadata,
     pub author: Metadata,
     pub date: Metadata,
-    pub style: Metadata
+    pub style: Metadata,
+    /// Any other metadata, such as `description` or `lang`, by name in the order it was first written
+    pub metadata: Vec<(std::string::String, MetadataValue)>,
+    /// The ids of the headings in other files that `ref` sections link to, by their `file#Section` target, as found by `resolve_references`
+    pub anchors: HashMap<std::string::String, std::string::String>
 }
 impl<'a> Ley<'a> {
-    pub fn new(mut source: &'a str, style: Metadata) -> Result<Self, ParseError<'a>> {
-        let mut token_stream = Vec::new();
-        while let Some(token) = Token::parse(&mut source) {
-            token_stream.push(token)
-        }
-        let mut token_stream = token_stream.as_slice();
-        let mut token_stream = TokenIter(&mut token_stream);
+    /// Parse a ley file, returning every error found if it is malformed
+    pub fn new(source: &'a str, style: Metadata) -> Result<Self, Vec<ParseError<'a>>> {
+        let mut errors = Vec::new();
+        let token_stream = Token::tokenize(source, &mut errors);
+        let mut token_stream = TokenIter::new(&token_stream, source);
 
         let mut lines = Vec::new();
         let (mut title, mut author, mut date, mut style) = (Metadata::NONE, Metadata::NONE, Metadata::NONE, style);
-        while let Some(_) = token_stream.peek() {
+        let mut metadata = Vec::new();
+        let mut parsed = Vec::new();
+        while token_stream.peek().is_some() {
+            parsed.extend(LeyLine::parse_lines(&mut token_stream, &mut errors));
+            // Only a `}` without a section to close is left
+            let _ = LeyLine::parse(&mut token_stream, &mut errors);
+        }
+        for ley_line in parsed {
             use LeyLine::*;
-            match LeyLine::parse(&mut token_stream)? {
-                Section { name: Some(name), contents, kind: SectionKind::Metadata } => {
-                    if let Some(&name) = name.get(0) {
-                        match name {
-                            "title" => title = Metadata::from_lines(contents)?,
-                            "author" => author = Metadata::from_lines(contents)?,
-                            "date" => date = Metadata::from_lines(contents)?,
-                            "style" => style = Metadata::from_lines(contents)?,
-                            _ => eprintln!("Warning: Unknown Metadata {}", name)
+            match ley_line {
+                Section { name: Some(name), contents, kind: SectionKind::Metadata, span } => {
+                    let value = match MetadataValue::from_lines(contents) {
+                        Ok(value) => value,
+                        Err(error) => {
+                            errors.push(error.at(span));
+                            continue
                         }
+                    };
+                    let name = name.to_string();
+                    match name.as_str() {
+                        "title" => *title = Some(value),
+                        "author" => *author = Some(value),
+                        "date" => *date = Some(value),
+                        "style" => *style = Some(value),
+                        _ => set_metadata(&mut metadata, name, value)
                     }
                 }
                 ley_line => lines.push(ley_line)
             }
         }
+        if !errors.is_empty() {
+            errors.sort_by_key(|error| error.span.start);
+            return Err(errors)
+        }
         let lines = LeyLines(lines);
-        
+
         Ok(Self {
             lines,
             title,
             author,
             date,
-            style
+            style,
+            metadata,
+            anchors: HashMap::new()
         })
     }
+    /// The name and value of every piece of metadata that is set, starting with the title, author, date and style
+    pub fn all_metadata(&self) -> impl Iterator<Item = (&str, &MetadataValue)> {
+        IntoIterator::into_iter([("title", &self.title), ("author", &self.author), ("date", &self.date), ("style", &self.style)])
+            .filter_map(|(name, value)| value.as_ref().map(|value| (name, value)))
+            .chain(self.metadata.iter().map(|(name, value)| (name.as_str(), value)))
+    }
+    /// The metadata called `name`, other than the title, author, date and style
+    pub fn get_metadata(&self, name: &str) -> Option<&MetadataValue> {
+        self.metadata.iter().find(|(existing, _)| existing == name).map(|(_, value)| value)
+    }
+    /// How dates are shown, as given by the `date-format` metadata, in the form described by `Date::format`.
+    /// Defaults to the iso format, `%Y-%m-%d`
+    pub fn date_format(&self) -> Cow<'_, str> {
+        self.get_metadata("date-format").map_or(Cow::Borrowed("%Y-%m-%d"), |format| Cow::Owned(format.to_string()))
+    }
+    /// A metadata value as it is shown in a rendered page, with dates in the date format of the file
+    pub fn show(&self, value: &MetadataValue) -> std::string::String {
+        value.format(&self.date_format())
+    }
 }
 
-pub struct Metadata(Option<std::string::String>);
+/// Set the metadata called `name`, replacing its value if it is already set
+pub(crate) fn set_metadata(metadata: &mut Vec<(std::string::String, MetadataValue)>, name: std::string::String, value: MetadataValue) {
+    match metadata.iter_mut().find(|(existing, _)| *existing == name) {
+        Some((_, existing)) => *existing = value,
+        None => metadata.push((name, value))
+    }
+}
+
+/// One of the title, author, date and style of a ley file, if it is set
+pub struct Metadata(pub(crate) Option<MetadataValue>);
 impl Metadata {
-    const NONE: Self = Self(None);
-    pub fn from_lines<'a>(mut ley_lines: LeyLines<'a>) -> Result<Self, ParseError> {
-        if ley_lines.len() == 1 {
-            if let LeyLine::Text { contents } = ley_lines.remove(0) {
-                Ok(Self(Some(format!("{}", contents))))
-            } else {
-                Err(ParseError::ExpectedString)
-            }
-        } else {
-            Err(ParseError::ExpectedString)
+    pub(crate) const NONE: Self = Self(None);
+    pub fn from_lines<'a>(ley_lines: LeyLines<'a>) -> Result<Self, ParseErrorKind<'a>> {
+        MetadataValue::from_lines(ley_lines).map(|value| Self(Some(value)))
+    }
+    /// The value as text, or `default` if it isn't set
+    pub fn default<'a>(&'a self, default: &'a str) -> Cow<'a, str> {
+        match self {
+            Self(Some(MetadataValue::Text(text))) => Cow::Borrowed(text),
+            Self(Some(value)) => Cow::Owned(value.to_string()),
+            Self(None) => Cow::Borrowed(default)
         }
     }
-    pub fn default<'a>(&'a self, default: &'a str) -> &'a str {
-        if let Self(Some(string)) = self {
-            string.as_str()
-        } else {
-            default
+    /// The value if it is a date
+    pub fn as_date(&self) -> Option<Date> {
+        match self {
+            Self(Some(MetadataValue::Date(date))) => Some(*date),
+            _ => None
         }
     }
 }
 impl Deref for Metadata {
-    type Target = Option<std::string::String>;
+    type Target = Option<MetadataValue>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -83,27 +134,210 @@ impl DerefMut for Metadata {
 }
 impl From<Option<std::string::String>> for Metadata {
     fn from(from: Option<std::string::String>) -> Self {
-        Self(from)
+        Self(from.map(MetadataValue::Text))
+    }
+}
+
+/// The value of a piece of metadata
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetadataValue {
+    Text(std::string::String),
+    /// Written as `true` or `false`
+    Bool(bool),
+    /// Written in the iso format, such as `2024-03-01`
+    Date(Date),
+    /// Written as unnamed metadata sections, such as `!: meta {Alice} !: meta {Bob}`
+    List(Vec<MetadataValue>)
+}
+impl MetadataValue {
+    pub fn from_lines<'a>(ley_lines: LeyLines<'a>) -> Result<Self, ParseErrorKind<'a>> {
+        if let [LeyLine::Text { contents, .. }] = ley_lines.as_slice() {
+            return Self::from_text(contents.to_string())
+        }
+        ley_lines.0.into_iter()
+            .filter(|ley_line| !matches!(ley_line, LeyLine::Comment { .. }))
+            .map(|ley_line| match ley_line {
+                LeyLine::Section { name: None, kind: SectionKind::Metadata, contents, .. } => Self::from_lines(contents),
+                _ => Err(ParseErrorKind::ExpectedMetadata)
+            })
+            .collect::<Result<_, _>>()
+            .map(Self::List)
+    }
+    /// Read text as a boolean or a date if it is one
+    pub fn from_text<'a>(text: std::string::String) -> Result<Self, ParseErrorKind<'a>> {
+        match text.as_str() {
+            "true" => Ok(Self::Bool(true)),
+            "false" => Ok(Self::Bool(false)),
+            _ if Date::is_iso(&text) => Date::parse(&text).map(Self::Date).ok_or(ParseErrorKind::InvalidDate(text)),
+            _ => Ok(Self::Text(text))
+        }
+    }
+    /// The value as it is shown in a rendered page, with dates in `date_format` and the items of lists separated by commas
+    pub fn format(&self, date_format: &str) -> std::string::String {
+        match self {
+            Self::Date(date) => date.format(date_format),
+            Self::List(items) => items.iter().map(|item| item.format(date_format)).collect::<Vec<_>>().join(", "),
+            _ => self.to_string()
+        }
+    }
+}
+impl Display for MetadataValue {
+    /// The value as text, with dates in the iso format and the items of lists separated by commas
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Text(text) => f.write_str(text),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Date(date) => write!(f, "{}", date),
+            Self::List(items) => {
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?
+                    }
+                    write!(f, "{}", item)?
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A calendar date, ordered from earliest to latest
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8
+}
+impl Date {
+    const MONTHS: [&'static str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+    const DAYS: [&'static str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+    /// Whether the text is in the form of an iso date, `YYYY-MM-DD`, whether or not it is a real one
+    fn is_iso(text: &str) -> bool {
+        text.len() == 10 && text.bytes().enumerate().all(|(index, byte)| if index == 4 || index == 7 { byte == b'-' } else { byte.is_ascii_digit() })
+    }
+    /// Parse an iso date, `YYYY-MM-DD`
+    pub fn parse(text: &str) -> Option<Self> {
+        if !Self::is_iso(text) {
+            return None
+        }
+        let date = Self {
+            year: text[..4].parse().ok()?,
+            month: text[5..7].parse().ok()?,
+            day: text[8..].parse().ok()?
+        };
+        if (1..=12).contains(&date.month) && date.day >= 1 && date.day <= date.days_in_month() {
+            Some(date)
+        } else {
+            None
+        }
+    }
+    fn days_in_month(self) -> u8 {
+        match self.month {
+            2 if self.year.is_multiple_of(4) && (!self.year.is_multiple_of(100) || self.year.is_multiple_of(400)) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31
+        }
+    }
+    /// The day of the week, counting from Sunday as 0
+    fn weekday(self) -> usize {
+        const OFFSETS: [i32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        // January and February count as the end of the year before, which for the year 0 is -1
+        let year = self.year as i32 - (self.month < 3) as i32;
+        (year + year.div_euclid(4) - year.div_euclid(100) + year.div_euclid(400) + OFFSETS[self.month as usize - 1] + self.day as i32).rem_euclid(7) as usize
+    }
+    /// The date written in `format`, where `%Y` is the year, `%m` and `%d` the month and day as two digits, `%e` the day without padding,
+    /// `%B` and `%b` the full and short name of the month, `%A` and `%a` the full and short name of the day of the week and `%%` a `%`
+    pub fn format(self, format: &str) -> std::string::String {
+        let mut formatted = std::string::String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                formatted.push(c);
+                continue
+            }
+            let month = Self::MONTHS[self.month as usize - 1];
+            match chars.next() {
+                Some('Y') => formatted.push_str(&format!("{:04}", self.year)),
+                Some('m') => formatted.push_str(&format!("{:02}", self.month)),
+                Some('d') => formatted.push_str(&format!("{:02}", self.day)),
+                Some('e') => formatted.push_str(&self.day.to_string()),
+                Some('B') => formatted.push_str(month),
+                Some('b') => formatted.push_str(&month[..3]),
+                Some('A') => formatted.push_str(Self::DAYS[self.weekday()]),
+                Some('a') => formatted.push_str(&Self::DAYS[self.weekday()][..3]),
+                Some('%') => formatted.push('%'),
+                // Anything else is left as it was written
+                Some(other) => {
+                    formatted.push('%');
+                    formatted.push(other)
+                },
+                None => formatted.push('%')
+            }
+        }
+        formatted
+    }
+}
+impl Display for Date {
+    /// The date in the iso format
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
     }
 }
 
 #[derive(Debug)]
 pub struct LeyLines<'a>(pub Vec<LeyLine<'a>>);
 impl <'a> LeyLines<'a> {
-    pub fn new(mut source: &'a str) -> Result<Self, ParseError> {
-        let mut token_stream = Vec::new();
-        while let Some(token) = Token::parse(&mut source) {
-            token_stream.push(token)
-        }
-        let mut token_stream = token_stream.as_slice();
-        let mut token_stream = TokenIter(&mut token_stream);
+    pub fn new(source: &'a str) -> Result<Self, Vec<ParseError<'a>>> {
+        let mut errors = Vec::new();
+        let token_stream = Token::tokenize(source, &mut errors);
+        let mut token_stream = TokenIter::new(&token_stream, source);
 
         let mut ley_lines = Vec::new();
-        while let Some(_) = token_stream.peek() {
-            ley_lines.push(LeyLine::parse(&mut token_stream)?)
+        while token_stream.peek().is_some() {
+            ley_lines.extend(LeyLine::parse_lines(&mut token_stream, &mut errors));
+            // Only a `}` without a section to close is left
+            let _ = LeyLine::parse(&mut token_stream, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(Self(ley_lines))
+        } else {
+            errors.sort_by_key(|error| error.span.start);
+            Err(errors)
+        }
+    }
+    /// The value of the attribute `name`, given as nested metadata such as `!width: meta {640}`
+    pub fn attribute(&self, name: &str) -> Option<&String<'a>> {
+        self.iter().find_map(|ley_line| match ley_line {
+            LeyLine::Section { name: Some(attribute), kind: SectionKind::Metadata, contents, .. } if *attribute == name => match contents.as_slice() {
+                [LeyLine::Text { contents, .. }] => Some(contents),
+                _ => None
+            },
+            _ => None
+        })
+    }
+    /// Display only the text of the ley lines, without any markup or metadata
+    pub fn plain(&self) -> Plain<'a, '_> {
+        Plain(self)
+    }
+    /// The text of a code section, exactly as written
+    pub fn code(&self) -> std::string::String {
+        let mut code = std::string::String::new();
+        for ley_line in self.iter() {
+            if let LeyLine::Text { contents, .. } = ley_line {
+                code.push_str(&contents.verbatim().to_string())
+            }
         }
-        
-        Ok(Self(ley_lines))
+        code
+    }
+    /// Iterate over the ley lines along with whether each was followed by whitespace before the next line.
+    /// Renderers may use this to space out inline lines such as text and links as they were in the source
+    pub fn spaced(&self) -> impl Iterator<Item = (&LeyLine<'a>, bool)> {
+        self.iter().enumerate().map(move |(index, ley_line)| {
+            let spaced = self.get(index + 1).is_some_and(|next| ley_line.span().end < next.span().start);
+            (ley_line, spaced)
+        })
     }
 }
 impl<'a> Deref for LeyLines<'a> {
@@ -118,72 +352,360 @@ impl<'a> DerefMut for LeyLines<'a> {
     }
 }
 
+/// Displays the text of ley lines, such as for the alt text of an image
+pub struct Plain<'a, 'b>(&'b LeyLines<'a>);
+impl<'a, 'b> Display for Plain<'a, 'b> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Only pieces of text are shown, so spaces are only written between them
+        for (ley_line, space) in inline(self.0, Self::is_text) {
+            if space {
+                f.write_str(" ")?
+            }
+            match ley_line {
+                LeyLine::Text { contents, .. } => write!(f, "{}", contents)?,
+                LeyLine::Section { contents, kind: SectionKind::Code | SectionKind::InlineCode | SectionKind::Math | SectionKind::InlineMath, .. } => for ley_line in contents.iter() {
+                    if let LeyLine::Text { contents, .. } = ley_line {
+                        write!(f, "{}", contents.verbatim())?
+                    }
+                },
+                LeyLine::Inline { contents, .. } | LeyLine::Section { contents, .. } => write!(f, "{}", contents.plain())?,
+                LeyLine::Comment { .. } => ()
+            }
+        }
+        Ok(())
+    }
+}
+impl<'a, 'b> Plain<'a, 'b> {
+    /// Whether a line has text to show
+    fn is_text(ley_line: &LeyLine) -> bool {
+        match ley_line {
+            LeyLine::Text { .. } | LeyLine::Inline { .. } => true,
+            LeyLine::Section { kind, .. } => matches!(kind, SectionKind::Section | SectionKind::Paragraph | SectionKind::Link | SectionKind::Code | SectionKind::InlineCode | SectionKind::Math | SectionKind::InlineMath),
+            LeyLine::Comment { .. } => false
+        }
+    }
+}
+
 /// A portion of a ley file
 #[derive(Debug)]
 pub enum LeyLine<'a> {
     Section {
         name: Option<String<'a>>,
         kind: SectionKind,
-        contents: LeyLines<'a>
+        contents: LeyLines<'a>,
+        span: Span
     },
     Text {
-        contents: String<'a>
+        contents: String<'a>,
+        span: Span
+    },
+    /// Inline markup, such as emphasis, which may nest
+    Inline {
+        kind: InlineKind,
+        contents: LeyLines<'a>,
+        span: Span
     },
-    Comment
+    /// A section written with `;` in place of `:`, which isn't rendered
+    Comment {
+        name: Option<String<'a>>,
+        /// The text within the braces, exactly as written. Unlike other sections it isn't parsed as ley
+        body: &'a str,
+        /// The comment exactly as written, from its `!` to its closing `}`
+        source: &'a str,
+        span: Span
+    }
 }
 impl<'a> LeyLine<'a> {
-    pub fn parse(token_stream: &mut TokenIter<'a, '_, '_>) -> Result<Self, ParseError<'a>> {
+    /// Parse ley lines up to the end of the source or the next `}`, which is left for the caller,
+    /// recording the errors in any line to `errors` and skipping past it
+    pub fn parse_lines(token_stream: &mut TokenIter<'a, '_>, errors: &mut Vec<ParseError<'a>>) -> Vec<Self> {
         use Token::*;
-        match token_stream.next()? {
-            Exclamation => {
-                let name = String::<'a>::parse(token_stream);
-                let comment = match token_stream.next()? {
-                    Colon => false,
-                    SemiColon => true,
-                    _ => return Err(ParseError::ExpectedColon)
-                };
-                let kind = match token_stream.next()? {
-                    Identifier(ident) => {
-                        if *token_stream.next()? != Token::OpenBrace {
-                            return Err(ParseError::ExpectedOpenBrace)
-                        }
-                        if comment {
-                            SectionKind::Section
-                        } else {
-                            SectionKind::new(ident)?
-                        }
-                    },
-                    OpenBrace => if name.is_none() { SectionKind::Paragraph } else { SectionKind::Section },
-                    _ => return Err(ParseError::ExpectedOpenBrace)
-                };
-                let mut contents = vec![];
-                while *token_stream.peek()? != CloseBrace {
-                    contents.push(Self::parse(token_stream)?)
-                }
-                let _ = token_stream.next();
-                Ok(
-                    if comment {
-                        Self::Comment
+        let mut pieces = Vec::new();
+        // The position in `pieces` of each marker that may yet begin markup, innermost last
+        let mut open: Vec<usize> = Vec::new();
+        // The positions in `open` of the markers of each kind of markup, so the one a marker ends is found without searching
+        let mut begun: [Vec<usize>; 5] = Default::default();
+        // The position of the marker ending the markup begun by each marker, found as soon as it is reached
+        let mut ends = HashMap::new();
+        while let Some(token) = token_stream.peek() {
+            match &token.value {
+                CloseBrace => break,
+                marker @ (Star | DoubleStar | Underscore | Backtick | Tilde) => {
+                    let after = token_stream.last_span().end;
+                    let _ = token_stream.next();
+                    let index = pieces.len();
+                    // Markup only ends right against the last of what it marks up, and only begins right against what it marks up,
+                    // so `2 * 3` is just text. A marker ends the innermost markup of its kind with something in it,
+                    // and any markup begun within that but not yet ended is just text.
+                    // Each marker is looked at once, so that parsing takes as long as the source is, however much markup is left unmatched
+                    let kind = InlineKind::new(marker) as usize;
+                    let ended = if index > 0 && token.span.start == after {
+                        begun[kind].iter().rev().take(2).find(|&&position| open[position] + 1 < index).copied()
                     } else {
-                        Self::Section {
-                            name,
-                            kind,
-                            contents: LeyLines(contents)
+                        None
+                    };
+                    match ended {
+                        Some(position) => {
+                            ends.insert(open[position], index);
+                            open.truncate(position);
+                            for begun in &mut begun {
+                                while begun.last().is_some_and(|&inner| inner >= position) {
+                                    begun.pop();
+                                }
+                            }
+                        },
+                        None => if token_stream.peek().is_some_and(|next| next.span.start == token.span.end && next.value != CloseBrace) {
+                            begun[kind].push(open.len());
+                            open.push(index)
                         }
                     }
-                )
+                    pieces.push(Piece::Marker(marker, token.span))
+                },
+                _ => if let Some(ley_line) = Self::parse(token_stream, errors) {
+                    pieces.push(Piece::Line(ley_line))
+                }
+            }
+        }
+        // The markup is built up from the lines within it, with the markup begun but not yet ended last
+        let mut markup: Vec<(InlineKind, Span, usize, Vec<Self>)> = Vec::new();
+        let mut lines = Vec::new();
+        for (index, piece) in pieces.into_iter().enumerate() {
+            let ley_line = match piece {
+                Piece::Line(ley_line) => ley_line,
+                Piece::Marker(marker, span) => if let Some(&end) = ends.get(&index) {
+                    markup.push((InlineKind::new(marker), span, end, Vec::new()));
+                    continue
+                } else if let Some((kind, start, _, contents)) = markup.pop_if(|(_, _, end, _)| *end == index) {
+                    Self::Inline {
+                        kind,
+                        contents: LeyLines(contents),
+                        span: start.to(span)
+                    }
+                } else {
+                    // Unmatched markup is just text
+                    Self::Text {
+                        contents: String::new(InlineKind::new(marker).symbol()),
+                        span
+                    }
+                }
+            };
+            match markup.last_mut() {
+                Some((_, _, _, contents)) => contents.push(ley_line),
+                None => lines.push(ley_line)
             }
+        }
+        lines
+    }
+    /// Parse the next ley line other than a marker of inline markup, or record the errors in it to `errors` and skip past it
+    fn parse(token_stream: &mut TokenIter<'a, '_>, errors: &mut Vec<ParseError<'a>>) -> Option<Self> {
+        use Token::*;
+        let token = token_stream.next()?;
+        match &token.value {
+            Exclamation => Self::parse_section(token.span, token_stream, errors),
             Identifier(ident) => {
-                Ok(Self::Text {
-                    contents: String::with(ident, token_stream)
+                Some(Self::Text {
+                    contents: String::with(ident, token_stream),
+                    span: token.span.to(token_stream.last_span())
                 })
             }
-            token => panic!("Unexpected {:?}, {:?}", token, token_stream)
+            // Only lexed after the header of a comment or of code, which take it as their body
+            Verbatim(body) => Some(Self::Text {
+                contents: String::split(body),
+                span: token.span
+            }),
+            Star | DoubleStar | Underscore | Backtick | Tilde => unreachable!("Markup is matched by `parse_lines`"),
+            CloseBrace => {
+                errors.push(ParseErrorKind::UnexpectedCloseBracket.at(token.span));
+                None
+            }
+            OpenBrace => {
+                errors.push(ParseErrorKind::UnexpectedToken("{").at(token.span));
+                // Most likely a section missing its header, so skip the whole body
+                token_stream.skip_body();
+                None
+            }
+            Colon => {
+                errors.push(ParseErrorKind::UnexpectedToken(":").at(token.span));
+                None
+            }
+            SemiColon => {
+                errors.push(ParseErrorKind::UnexpectedToken(";").at(token.span));
+                None
+            }
+        }
+    }
+    fn parse_section(start: Span, token_stream: &mut TokenIter<'a, '_>, errors: &mut Vec<ParseError<'a>>) -> Option<Self> {
+        let (name, comment, kind) = match Self::parse_header(token_stream) {
+            Ok(header) => header,
+            Err(error) => {
+                errors.push(error);
+                token_stream.recover();
+                return None
+            }
+        };
+        if comment {
+            // Comments aren't ley either, so their body may hold any text
+            let body = match token_stream.verbatim_body() {
+                Some(body) => body,
+                None => {
+                    errors.push(ParseErrorKind::UnclosedSection.at(start));
+                    return None
+                }
+            };
+            let span = start.to(token_stream.last_span());
+            return Some(Self::Comment {
+                name,
+                body,
+                source: token_stream.source(span),
+                span
+            })
+        }
+        let contents = if matches!(kind, Ok(SectionKind::Code | SectionKind::InlineCode | SectionKind::Math | SectionKind::InlineMath)) {
+            // Code and maths aren't ley, so they are kept exactly as written
+            let open = token_stream.last_span();
+            token_stream.verbatim_body().map(|code| vec![Self::Text {
+                contents: String::code(code),
+                span: Span::new(open.end, token_stream.last_span().start)
+            }])
+        } else {
+            Self::parse_body(token_stream, errors)
+        };
+        let contents = match contents {
+            Some(contents) => contents,
+            None => {
+                errors.push(ParseErrorKind::UnclosedSection.at(start));
+                return None
+            }
+        };
+        let span = start.to(token_stream.last_span());
+        match kind {
+            Ok(kind) => {
+                let contents = LeyLines(contents);
+                if kind.check(&name, &contents, span, errors) {
+                    Some(Self::Section {
+                        name,
+                        kind,
+                        contents,
+                        span
+                    })
+                } else {
+                    None
+                }
+            },
+            Err(error) => {
+                errors.push(error);
+                None
+            }
+        }
+    }
+    /// Parse the ley lines of a section body up to and including its `}`, or `None` if it is never closed
+    fn parse_body(token_stream: &mut TokenIter<'a, '_>, errors: &mut Vec<ParseError<'a>>) -> Option<Vec<Self>> {
+        let contents = Self::parse_lines(token_stream, errors);
+        token_stream.next()?;
+        Some(contents)
+    }
+    /// Parse the name, separator and kind of a section up to and including its `{`
+    fn parse_header(token_stream: &mut TokenIter<'a, '_>) -> Result<Header<'a>, ParseError<'a>> {
+        use Token::*;
+        let name = String::<'a>::parse(token_stream);
+        let separator = token_stream.expect()?;
+        let comment = match separator.value {
+            Colon => false,
+            SemiColon => true,
+            _ => return Err(ParseErrorKind::ExpectedColon.at(separator.span))
+        };
+        let _ = token_stream.next();
+        let kind = token_stream.expect()?;
+        let kind = match kind.value {
+            Identifier(ident) => {
+                let _ = token_stream.next();
+                let brace = token_stream.expect()?;
+                if brace.value != Token::OpenBrace {
+                    return Err(ParseErrorKind::ExpectedOpenBrace.at(brace.span))
+                }
+                if comment {
+                    Ok(SectionKind::Section)
+                } else {
+                    SectionKind::new(ident).map_err(|error| error.at(kind.span))
+                }
+            },
+            OpenBrace => Ok(if name.is_none() { SectionKind::Paragraph } else { SectionKind::Section }),
+            _ => return Err(ParseErrorKind::ExpectedOpenBrace.at(kind.span))
+        };
+        let _ = token_stream.next();
+        Ok((name, comment, kind))
+    }
+    /// The region of source the ley line was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Section { span, .. } | Self::Text { span, .. } | Self::Inline { span, .. } | Self::Comment { span, .. } => *span
         }
     }
 }
 
-#[derive(Debug)]
+/// A ley line, or a marker of inline markup that is yet to be matched with the marker ending it
+enum Piece<'a, 'b> {
+    Line(LeyLine<'a>),
+    Marker(&'b Token<'a>, Span)
+}
+
+/// The name of a section, whether it is a comment, and its kind.
+/// An unknown kind is only reported once the body has also been checked, so it is kept separate from other header errors
+type Header<'a> = (Option<String<'a>>, bool, Result<SectionKind, ParseError<'a>>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InlineKind {
+    Emphasis,
+    Strong,
+    Underline,
+    Strikethrough,
+    Monospace
+}
+impl InlineKind {
+    /// Panics if the token is not a markup token
+    fn new(from: &Token) -> Self {
+        match from {
+            Token::Star => Self::Emphasis,
+            Token::DoubleStar => Self::Strong,
+            Token::Underscore => Self::Underline,
+            Token::Tilde => Self::Strikethrough,
+            Token::Backtick => Self::Monospace,
+            token => unreachable!("{:?} is not markup", token)
+        }
+    }
+    /// The kind of markup with the name given by `name`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "emphasis" => Some(Self::Emphasis),
+            "strong" => Some(Self::Strong),
+            "underline" => Some(Self::Underline),
+            "strikethrough" => Some(Self::Strikethrough),
+            "monospace" => Some(Self::Monospace),
+            _ => None
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Emphasis => "emphasis",
+            Self::Strong => "strong",
+            Self::Underline => "underline",
+            Self::Strikethrough => "strikethrough",
+            Self::Monospace => "monospace"
+        }
+    }
+    /// The markup character(s) surrounding the inline contents
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::Emphasis => "*",
+            Self::Strong => "**",
+            Self::Underline => "_",
+            Self::Strikethrough => "~",
+            Self::Monospace => "`"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SectionKind {
     Section,
     Paragraph,
@@ -191,10 +713,35 @@ pub enum SectionKind {
 
     Link,
     Image,
-    Code
+    /// A block of code, named by its language
+    Code,
+    /// Code within a line of text, named by its language
+    InlineCode,
+    /// A table of contents of the named sections, with an optional title as its name
+    Toc,
+    /// A note referred to by number where it is written, and listed with the other notes at the end
+    Footnote,
+    /// A link to a named section, given as `Section` in this file or `file#Section` in another file of the directory
+    Ref,
+    /// A bulleted list, where each unnamed section or run of text is an item
+    List,
+    /// A numbered list, like `List`, counting from an optional `start`
+    Ordered,
+    /// A table of `Row` and `Header` sections, with the optional `align`ment of each column
+    Table,
+    /// A row of `Cell` sections in a table
+    Row,
+    /// A row of a table whose cells head their columns
+    Header,
+    /// A cell of a row, which may span several columns with `colspan`
+    Cell,
+    /// Maths written in TeX, displayed on its own, and named by an optional description
+    Math,
+    /// Maths written in TeX within a line of text, named by an optional description
+    InlineMath
 }
 impl SectionKind {
-    pub fn new(from: &str) -> Result<Self, ParseError> {
+    pub fn new(from: &str) -> Result<Self, ParseErrorKind<'_>> {
         match from {
             "section" => Ok(Self::Section),
             "paragraph" | "para" | "p" => Ok(Self::Paragraph),
@@ -202,51 +749,197 @@ impl SectionKind {
             "link" => Ok(Self::Link),
             "image" | "img" => Ok(Self::Image),
             "code" | "lang" => Ok(Self::Code),
-            kind => Err(ParseError::UnknownSection(kind))
+            "inline" => Ok(Self::InlineCode),
+            "toc" | "contents" => Ok(Self::Toc),
+            "footnote" | "note" => Ok(Self::Footnote),
+            "ref" => Ok(Self::Ref),
+            "list" => Ok(Self::List),
+            "ordered" => Ok(Self::Ordered),
+            "table" => Ok(Self::Table),
+            "row" => Ok(Self::Row),
+            "header" => Ok(Self::Header),
+            "cell" => Ok(Self::Cell),
+            "math" | "maths" => Ok(Self::Math),
+            "inline-math" | "inline-maths" => Ok(Self::InlineMath),
+            kind => Err(ParseErrorKind::UnknownSection(kind))
         }
     }
+    /// The canonical name of the kind, as written in a section header
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Section => "section",
+            Self::Paragraph => "paragraph",
+            Self::Metadata => "meta",
+            Self::Link => "link",
+            Self::Image => "image",
+            Self::Code => "code",
+            Self::InlineCode => "inline",
+            Self::Toc => "toc",
+            Self::Footnote => "footnote",
+            Self::Ref => "ref",
+            Self::List => "list",
+            Self::Ordered => "ordered",
+            Self::Table => "table",
+            Self::Row => "row",
+            Self::Header => "header",
+            Self::Cell => "cell",
+            Self::Math => "math",
+            Self::InlineMath => "inline-math"
+        }
+    }
+    /// The names of the attributes, given as nested metadata, that the kind of section accepts
+    pub fn attributes(self) -> &'static [&'static str] {
+        match self {
+            Self::Image => &["width", "height", "title", "caption"],
+            Self::Toc => &["depth"],
+            Self::Ordered => &["start"],
+            Self::Table => &["align"],
+            Self::Cell => &["colspan"],
+            _ => &[]
+        }
+    }
+    /// Record any errors in a section of this kind to `errors`, returning whether it is valid
+    pub(crate) fn check<'a>(self, name: &Option<String<'a>>, contents: &LeyLines<'a>, span: Span, errors: &mut Vec<ParseError<'a>>) -> bool {
+        let error_count = errors.len();
+        if self == Self::Image && name.is_none() {
+            errors.push(ParseErrorKind::MissingImageSource.at(span))
+        }
+        if self == Self::Ref {
+            match name.as_ref().map(|name| name.to_string()) {
+                None => errors.push(ParseErrorKind::MissingReference.at(span)),
+                // Another file is linked to by its name, so it must be a ley file in the same directory rather than any url
+                Some(target) => if let Some((file, _)) = target.split_once('#') {
+                    if file.is_empty() || file.starts_with('.') || file.contains(|c: char| matches!(c, '/' | '\\' | ':' | '?' | '%') || c.is_control()) {
+                        errors.push(ParseErrorKind::InvalidReference(target.clone()).at(span))
+                    }
+                }
+            }
+        }
+        if !self.attributes().is_empty() {
+            for ley_line in contents.iter() {
+                if let LeyLine::Section { name: Some(name), kind: Self::Metadata, contents, span } = ley_line {
+                    let name = name.to_string();
+                    if !self.attributes().contains(&name.as_str()) {
+                        errors.push(ParseErrorKind::UnknownAttribute(name).at(*span))
+                    } else if !matches!(contents.as_slice(), [LeyLine::Text { .. }]) {
+                        errors.push(ParseErrorKind::ExpectedString.at(*span))
+                    } else if matches!(self, Self::Toc | Self::Ordered | Self::Cell) && !contents.plain().to_string().parse::<usize>().is_ok_and(|depth| depth > 0) {
+                        errors.push(ParseErrorKind::ExpectedNumber.at(*span))
+                    } else if self == Self::Table {
+                        let alignments = contents.plain().to_string();
+                        if let Some(alignment) = alignments.split(' ').find(|alignment| !matches!(*alignment, "left" | "center" | "right")) {
+                            errors.push(ParseErrorKind::InvalidAlignment(alignment.to_string()).at(*span))
+                        }
+                    }
+                }
+            }
+        }
+        // Tables are made of rows, and rows of cells, so that each format can lay out their columns
+        if matches!(self, Self::Table | Self::Row | Self::Header) {
+            for ley_line in contents.iter() {
+                match (self, ley_line) {
+                    (_, LeyLine::Comment { .. } | LeyLine::Section { kind: Self::Metadata, .. }) => (),
+                    (Self::Table, LeyLine::Section { kind: Self::Row | Self::Header, .. }) => (),
+                    (Self::Row | Self::Header, LeyLine::Section { kind: Self::Cell, .. }) => (),
+                    (Self::Table, other) => errors.push(ParseErrorKind::ExpectedRow.at(other.span())),
+                    (_, other) => errors.push(ParseErrorKind::ExpectedCell.at(other.span()))
+                }
+            }
+        }
+        errors.len() == error_count
+    }
 }
 
+/// Text from a ley file, made of words and the whitespace that separated them in the source.
+/// Displaying it collapses each run of whitespace to a single space, while `verbatim` preserves it
 #[derive(Clone, Debug)]
-pub struct String<'a>(Vec<&'a str>);
+pub struct String<'a>(Vec<Fragment<'a>>);
 impl<'a> String<'a> {
     pub fn new(string: &'a str) -> Self {
-        Self(vec![string])
+        Self(vec![Fragment::Word(string)])
     }
-    pub fn parse(tokens: &mut TokenIter<'a, '_, '_>) -> Option<Self> {
-        let mut string = Self(vec![]);
-        while let Some(Token::Identifier(ident)) = tokens.peek() {
-            tokens.next();
-            string.push(ident)
+    pub fn parse(tokens: &mut TokenIter<'a, '_>) -> Option<Self> {
+        let string = tokens.next_if(|token| matches!(token, Token::Identifier(_)))?;
+        match string.value {
+            Token::Identifier(ident) => Some(Self::with(ident, tokens)),
+            _ => None
         }
-        if string.len() != 0 {
-            Some(string)
-        } else {
-            None
+    }
+    /// Continue the string starting with the word that was just consumed from `tokens`
+    pub fn with(string: &'a str, tokens: &mut TokenIter<'a, '_>) -> Self {
+        let mut string = Self(vec![Fragment::Word(string)]);
+        let mut end = tokens.last_span().end;
+        while let Some(token) = tokens.next_if(|token| matches!(token, Token::Identifier(_))) {
+            if let Token::Identifier(ident) = token.value {
+                let space = tokens.source(Span::new(end, token.span.start));
+                if !space.is_empty() {
+                    string.push(Fragment::Space(space))
+                }
+                string.push(Fragment::Word(ident));
+                end = token.span.end
+            }
         }
+        string
+    }
+    /// The words of `text` along with the whitespace between them, kept as it is
+    pub fn split(text: &'a str) -> Self {
+        let mut string = Self(vec![]);
+        let mut rest = text;
+        while !rest.is_empty() {
+            let space = rest.starts_with(char::is_whitespace);
+            let end = rest.find(|c: char| c.is_whitespace() != space).unwrap_or(rest.len());
+            string.push(if space { Fragment::Space(&rest[..end]) } else { Fragment::Word(&rest[..end]) });
+            rest = &rest[end..]
+        }
+        string
     }
-    pub fn with(string: &'a str, tokens: &mut TokenIter<'a, '_, '_>) -> Self {
-        let mut string = Self(vec![string]);
-        while let Some(Token::Identifier(ident)) = tokens.peek() {
-            tokens.next();
-            string.push(ident)
+    /// The lines of code in `source` separated by newlines, without surrounding blank lines or the indentation common to every line
+    pub fn code(source: &'a str) -> Self {
+        let mut lines: Vec<&str> = source.lines().map(str::trim_end).collect();
+        let blank = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+        lines.drain(..blank);
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        let indent = lines.iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+            .min()
+            .unwrap_or(0);
+        let mut string = Self(vec![]);
+        for line in lines {
+            if !string.is_empty() {
+                string.push(Fragment::Space("\n"))
+            }
+            string.push(Fragment::Word(line.get(indent..).unwrap_or("")))
         }
         string
     }
-    pub fn from_lines(mut ley_lines: LeyLines<'a>) -> Result<Self, ParseError> {
+    pub fn from_lines(mut ley_lines: LeyLines<'a>) -> Result<Self, ParseErrorKind<'a>> {
         if ley_lines.len() == 1 {
-            if let LeyLine::Text { contents } = ley_lines.remove(0) {
+            if let LeyLine::Text { contents, .. } = ley_lines.remove(0) {
                 Ok(contents)
             } else {
-                Err(ParseError::ExpectedString)
+                Err(ParseErrorKind::ExpectedString)
             }
         } else {
-            Err(ParseError::ExpectedString)
+            Err(ParseErrorKind::ExpectedString)
         }
     }
+    /// The words of the string, without the whitespace between them
+    pub fn words(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.iter().filter_map(|fragment| match fragment {
+            Fragment::Word(word) => Some(*word),
+            Fragment::Space(_) => None
+        })
+    }
+    /// Display the string with its whitespace exactly as in the source
+    pub fn verbatim(&self) -> Verbatim<'a, '_> {
+        Verbatim(self)
+    }
 }
 impl<'a> Deref for String<'a> {
-    type Target = Vec<&'a str>;
+    type Target = Vec<Fragment<'a>>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
@@ -258,23 +951,95 @@ impl<'a> DerefMut for String<'a> {
 }
 impl<'a> Display for String<'a> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let mut iter = self.iter();
-        if let Some(ident) = iter.next() {
-            write!(f, "{}", ident)?
-        }
-        for ident in iter {
-            write!(f, " {}", ident)?
+        for fragment in self.iter() {
+            match fragment {
+                Fragment::Word(word) => f.write_str(word)?,
+                Fragment::Space(_) => f.write_str(" ")?
+            }
         }
         Ok(())
     }
 }
 impl<'a> PartialEq<&str> for String<'a> {
+    /// Compares as displayed, with whitespace collapsed
     fn eq(&self, other: &&str) -> bool {
-        if let Some(&value) = self.0.get(0) {
-            value == *other
-        } else {
-            false
+        let mut rest = *other;
+        for fragment in self.iter() {
+            let text = match fragment {
+                Fragment::Word(word) => word,
+                Fragment::Space(_) => " "
+            };
+            match rest.strip_prefix(text) {
+                Some(remaining) => rest = remaining,
+                None => return false
+            }
         }
+        rest.is_empty()
+    }
+}
+
+/// A part of a `String`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fragment<'a> {
+    /// Text as it was written, which is the contents of the quotes for a quoted string
+    Word(&'a str),
+    /// The whitespace between two words
+    Space(&'a str)
+}
+
+/// Displays a `String` with its original whitespace
+pub struct Verbatim<'a, 'b>(&'b String<'a>);
+impl<'a, 'b> Display for Verbatim<'a, 'b> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for fragment in self.0.iter() {
+            match fragment {
+                Fragment::Word(text) | Fragment::Space(text) => f.write_str(text)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A range of bytes within the source of a ley file
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+    /// A span covering both `self` and `other`
+    pub fn to(self, other: Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+    /// The 1-based line and column of the start of the span
+    pub fn location(self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+    }
+}
+
+/// A value along with the region of source it came from
+#[derive(PartialEq, Eq, Debug)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span: Span
+}
+impl<T> Spanned<T> {
+    pub fn new(value: T, start: usize, end: usize) -> Self {
+        Self {
+            value,
+            span: Span::new(start, end)
+        }
+    }
+}
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.value
     }
 }
 
@@ -286,6 +1051,8 @@ pub enum Token<'a> {
     Exclamation,
     Colon,
     SemiColon,
+    /// The body of a comment, or of code or maths, which isn't ley. Quotes fencing it are left out
+    Verbatim(&'a str),
     Star,
     DoubleStar,
     Backtick,
@@ -293,152 +1060,465 @@ pub enum Token<'a> {
     Tilde
 }
 impl<'a> Token<'a> {
-    pub fn parse(from: &mut &'a str) -> Option<Self> {
-        let mut ident = None;
+    /// Split all of `source` into tokens, recording any errors to `errors`
+    pub fn tokenize(source: &'a str, errors: &mut Vec<ParseError<'a>>) -> Vec<Spanned<Self>> {
+        let mut tokens = Vec::new();
         let mut pos = 0;
-        let mut chars = from.chars();
-        while let Some(c) = chars.next() {
+        while let Some(token) = Self::parse(source, &mut pos) {
+            match token {
+                Ok(token) => {
+                    let verbatim = token.value == Self::OpenBrace && Self::opens_verbatim(&tokens);
+                    tokens.push(token);
+                    // The body is found in the source before it could be lexed as ley, which it may not be
+                    if verbatim {
+                        let (body, end) = Self::verbatim(source, pos).unwrap_or((&source[pos..], source.len()));
+                        tokens.push(Spanned::new(Self::Verbatim(body), pos, end));
+                        pos = end
+                    }
+                },
+                Err(error) => errors.push(error)
+            }
+        }
+        tokens
+    }
+    /// Whether a `{` following `tokens` opens the body of a comment, or of code or maths, as in `!name; {` or `!name: code {`
+    fn opens_verbatim(tokens: &[Spanned<Self>]) -> bool {
+        let mut before = tokens.iter().rev().map(|token| &token.value);
+        let (kind, separator) = match before.next() {
+            Some(Self::Identifier(kind)) => (Some(*kind), before.next()),
+            separator => (None, separator)
+        };
+        let verbatim = match separator {
+            Some(Self::SemiColon) => true,
+            Some(Self::Colon) => kind.and_then(|kind| SectionKind::new(kind).ok())
+                .is_some_and(|kind| matches!(kind, SectionKind::Code | SectionKind::InlineCode | SectionKind::Math | SectionKind::InlineMath)),
+            _ => false
+        };
+        // The name, if there is one, comes between the separator and the `!`
+        verbatim && before.find(|token| !matches!(token, Self::Identifier(_))) == Some(&Self::Exclamation)
+    }
+    /// Find the end of a body that isn't ley, from `start` just after its `{`, giving its text and where its `}` is.
+    /// A body starting with a run of quotes is fenced by them, and ends at the first run as long that is followed by its `}`.
+    /// Any other body ends at the `}` balancing the braces within it
+    pub(crate) fn verbatim(source: &'a str, start: usize) -> Option<(&'a str, usize)> {
+        let body = &source[start..];
+        let fenced = body.trim_start();
+        let quotes = fenced.len() - fenced.trim_start_matches('"').len();
+        if quotes > 0 {
+            let inner = &fenced[quotes..];
+            if let Some(end) = inner.find(&fenced[..quotes]) {
+                let after = inner[end + quotes..].trim_start();
+                if after.starts_with('}') {
+                    return Some((&inner[..end], source.len() - after.len()))
+                }
+            }
+        }
+        let mut depth = 0usize;
+        for (index, c) in body.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Some((&body[..index], start + index)),
+                '}' => depth -= 1,
+                _ => ()
+            }
+        }
+        None
+    }
+    /// Parse the next token found at or after the byte offset `pos`, moving `pos` past it
+    pub fn parse(source: &'a str, pos: &mut usize) -> Option<Result<Spanned<Self>, ParseError<'a>>> {
+        let mut ident = None;
+        let offset = *pos;
+        let mut chars = source[offset..].char_indices().map(|(index, c)| (offset + index, c));
+        while let Some((index, c)) = chars.next() {
             match c {
                 ' ' | '\n' | '\r' | '\t' => if let Some(ident) = ident {
-                    let to_return = Some(Self::Identifier(&from[ident..pos]));
-                    *from = &from[pos+1..];
-                    return to_return;
+                    *pos = index + 1;
+                    return Some(Ok(Spanned::new(Self::Identifier(&source[ident..index]), ident, index)));
                 }
-                '{' => {
-                    return if let Some(ident) = ident {
-                        let to_return = Some(Self::Identifier(&from[ident..pos]));
-                        *from = &from[pos..];
-                        to_return
-                    } else {
-                        let to_return = Some(Self::OpenBrace);
-                        *from = &from[pos+1..];
-                        to_return
-                    }
+                '{' | '}' | '!' | ':' | ';' | '"' if ident.is_some() => {
+                    let ident = ident?;
+                    *pos = index;
+                    return Some(Ok(Spanned::new(Self::Identifier(&source[ident..index]), ident, index)));
                 }
-                '}' => {
-                    return if let Some(ident) = ident {
-                        let to_return = Some(Self::Identifier(&from[ident..pos]));
-                        *from = &from[pos..];
-                        to_return
-                    } else {
-                        let to_return = Some(Self::CloseBrace);
-                        *from = &from[pos+1..];
-                        to_return
-                    }
-                }
-                '!' => {
-                    return if let Some(ident) = ident {
-                        let to_return = Some(Self::Identifier(&from[ident..pos]));
-                        *from = &from[pos..];
-                        to_return
-                    } else {
-                        let to_return = Some(Self::Exclamation);
-                        *from = &from[pos+1..];
-                        to_return
-                    }
-                },
-                ':' => {
-                    return if let Some(ident) = ident {
-                        let to_return = Some(Self::Identifier(&from[ident..pos]));
-                        *from = &from[pos..];
-                        to_return
-                    } else {
-                        let to_return = Some(Self::Colon);
-                        *from = &from[pos+1..];
-                        to_return
-                    };
-                },
-                ';' => {
-                    return if let Some(ident) = ident {
-                        let to_return = Some(Self::Identifier(&from[ident..pos]));
-                        *from = &from[pos..];
-                        to_return
-                    } else {
-                        let to_return = Some(Self::SemiColon);
-                        *from = &from[pos+1..];
-                        to_return
+                '{' | '}' | '!' | ':' | ';' => {
+                    let to_return = match c {
+                        '{' => Self::OpenBrace,
+                        '}' => Self::CloseBrace,
+                        '!' => Self::Exclamation,
+                        ':' => Self::Colon,
+                        _ => Self::SemiColon
                     };
+                    *pos = index + 1;
+                    return Some(Ok(Spanned::new(to_return, index, index + 1)));
                 }
                 '"' => {
-                    return if let Some(ident) = ident {
-                        let to_return = Some(Self::Identifier(&from[ident..pos]));
-                        *from = &from[pos..];
-                        to_return
-                    } else {
-                        let mut end = pos;
-                        if chars.next()? == '"' {
-                            loop {
-                                end += 1;
-                                if chars.next()? == '"' {
-                                    end += 1;
-                                    if chars.next()? == '"' {
-                                        break
+                    let double = chars.clone().next().map(|(_, c)| c) == Some('"');
+                    let mut unclosed = || {
+                        *pos = source.len();
+                        Some(Err(ParseErrorKind::UnclosedString.at(Span::new(index, index + 1))))
+                    };
+                    // Double quotes preserve the inner quotes
+                    let (start, end) = if double {
+                        chars.next();
+                        loop {
+                            match chars.next() {
+                                Some((_, '"')) => if let Some((end, c)) = chars.next() {
+                                    if c == '"' {
+                                        break (index + 1, end)
                                     }
-                                }
+                                } else {
+                                    return unclosed()
+                                },
+                                Some(_) => (),
+                                None => return unclosed()
                             }
-                        } else {
-                            while chars.next()? != '"' {
-                                end += 1
+                        }
+                    } else {
+                        loop {
+                            match chars.next() {
+                                Some((end, '"')) => break (index + 1, end),
+                                Some(_) => (),
+                                None => return unclosed()
                             }
-                            end += 1
                         }
-                        let to_return = Some(Self::Identifier(&from[pos+1..end+1]));
-                        *from = &from[end+2..];
-                        to_return
                     };
+                    *pos = end + 1;
+                    return Some(Ok(Spanned::new(Self::Identifier(&source[start..end]), index, end + 1)));
+                }
+                '*' | '_' | '`' | '~' => if let Some(ident) = ident {
+                    // Markup only ends a word when the word doesn't continue after it, so `snake_case` remains whole
+                    let mut after = chars.clone().map(|(_, c)| c).skip_while(|&c| Self::is_markup(c));
+                    if after.next().is_none_or(|c| !c.is_alphanumeric()) {
+                        *pos = index;
+                        return Some(Ok(Spanned::new(Self::Identifier(&source[ident..index]), ident, index)));
+                    }
+                } else {
+                    let (to_return, end) = match c {
+                        '*' if chars.clone().next().map(|(_, c)| c) == Some('*') => {
+                            chars.next();
+                            (Self::DoubleStar, index + 2)
+                        },
+                        '*' => (Self::Star, index + 1),
+                        '_' => (Self::Underscore, index + 1),
+                        '`' => (Self::Backtick, index + 1),
+                        _ => (Self::Tilde, index + 1)
+                    };
+                    *pos = end;
+                    return Some(Ok(Spanned::new(to_return, index, end)));
                 }
-                _ => if ident == None {
-                    ident = Some(pos)
+                _ => if ident.is_none() {
+                    ident = Some(index)
                 }
             }
-            pos += 1
         }
-        None
+        *pos = source.len();
+        let ident = ident?;
+        Some(Ok(Spanned::new(Self::Identifier(&source[ident..]), ident, source.len())))
+    }
+    fn is_markup(c: char) -> bool {
+        matches!(c, '*' | '_' | '`' | '~')
     }
 }
 #[derive(Debug)]
-pub struct TokenIter<'a, 'b, 'c>(&'c mut &'b [Token<'a>]);
-impl<'a, 'b, 'c> TokenIter<'a, 'b, 'c> {
-    pub fn peek<'d>(&'d self) -> Option<&'b Token<'a>> {
-        self.0.get(0)
-    }
+pub struct TokenIter<'a, 'b> {
+    tokens: &'b [Spanned<Token<'a>>],
+    source: &'a str,
+    position: usize
 }
-impl<'a, 'b, 'c> Iterator for TokenIter<'a, 'b, 'c> {
-    type Item = &'b Token<'a>;
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(token) = self.0.get(0) {
-            *self.0 = &self.0[1..];
+impl<'a, 'b> TokenIter<'a, 'b> {
+    /// Iterate over `tokens`, which were lexed from `source`
+    pub fn new(tokens: &'b [Spanned<Token<'a>>], source: &'a str) -> Self {
+        Self {
+            tokens,
+            source,
+            position: 0
+        }
+    }
+    pub fn peek(&self) -> Option<&'b Spanned<Token<'a>>> {
+        self.tokens.get(self.position)
+    }
+    /// Consume the next token only if it satisfies `predicate`
+    pub fn next_if(&mut self, predicate: impl FnOnce(&Token<'a>) -> bool) -> Option<&'b Spanned<Token<'a>>> {
+        let token = self.peek()?;
+        if predicate(&token.value) {
+            self.position += 1;
             Some(token)
         } else {
             None
         }
     }
+    /// The source text covered by `span`
+    pub fn source(&self, span: Span) -> &'a str {
+        &self.source[span.start..span.end]
+    }
+    /// Peek at the next token, which is an error if the input has ended
+    pub fn expect(&self) -> Result<&'b Spanned<Token<'a>>, ParseError<'a>> {
+        self.peek().ok_or_else(|| {
+            let end = self.tokens.last().map_or(0, |token| token.span.end);
+            ParseErrorKind::EndOfFile.at(Span::new(end, end))
+        })
+    }
+    /// Skip the remains of a section with a malformed header; its body if it has one, otherwise up to the next section or the end of the enclosing one
+    pub fn recover(&mut self) {
+        while let Some(token) = self.peek() {
+            match token.value {
+                Token::OpenBrace => {
+                    self.position += 1;
+                    self.skip_body();
+                    return
+                }
+                Token::CloseBrace | Token::Exclamation => return,
+                _ => self.position += 1
+            }
+        }
+    }
+    /// Skip to just after the `}` closing the body that was just opened, returning whether it was found
+    pub fn skip_body(&mut self) -> bool {
+        let mut depth = 1;
+        for token in self.by_ref() {
+            match token.value {
+                Token::OpenBrace => depth += 1,
+                Token::CloseBrace => depth -= 1,
+                _ => ()
+            }
+            if depth == 0 {
+                return true
+            }
+        }
+        false
+    }
+    /// Skip the body that was just opened, which was lexed as a single `Verbatim` token, returning its text,
+    /// or `None` if it is never closed
+    pub fn verbatim_body(&mut self) -> Option<&'a str> {
+        let body = match self.next_if(|token| matches!(token, Token::Verbatim(_)))?.value {
+            Token::Verbatim(body) => body,
+            _ => unreachable!()
+        };
+        self.next_if(|token| *token == Token::CloseBrace)?;
+        Some(body)
+    }
+    /// The span of the most recently consumed token
+    pub fn last_span(&self) -> Span {
+        self.position.checked_sub(1).and_then(|last| self.tokens.get(last)).map_or(Span::default(), |token| token.span)
+    }
+}
+impl<'a, 'b> Iterator for TokenIter<'a, 'b> {
+    type Item = &'b Spanned<Token<'a>>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.tokens.get(self.position)?;
+        self.position += 1;
+        Some(token)
+    }
+}
+
+/// An error in a ley file along with where it occurred
+#[derive(Debug)]
+pub struct ParseError<'a> {
+    pub kind: ParseErrorKind<'a>,
+    pub span: Span
+}
+impl<'a> ParseError<'a> {
+    /// Describe the error with its location and an excerpt of `source`, the contents of `file`
+    pub fn report<'b>(&'b self, file: &'b str, source: &'b str) -> Report<'a, 'b> {
+        Report {
+            error: self,
+            file,
+            source
+        }
+    }
+}
+impl<'a> Display for ParseError<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.kind, f)
+    }
 }
 
-pub enum ParseError<'a> {
+#[derive(Debug)]
+pub enum ParseErrorKind<'a> {
     EndOfFile,
     UnclosedSection,
+    UnclosedString,
     UnexpectedCloseBracket,
+    UnexpectedToken(&'static str),
     UnknownSection(&'a str),
     ExpectedColon,
     ExpectedOpenBrace,
-    ExpectedString
+    ExpectedString,
+    ExpectedMetadata,
+    ExpectedNumber,
+    ExpectedRow,
+    ExpectedCell,
+    InvalidAlignment(std::string::String),
+    InvalidDate(std::string::String),
+    MissingImageSource,
+    MissingReference,
+    UnknownAttribute(std::string::String),
+    UnknownReference(std::string::String),
+    InvalidReference(std::string::String)
 }
-impl<'a> From<std::option::NoneError> for ParseError<'a> {
-    fn from(_: std::option::NoneError) -> Self {
-        ParseError::EndOfFile
+impl<'a> ParseErrorKind<'a> {
+    pub fn at(self, span: Span) -> ParseError<'a> {
+        ParseError {
+            kind: self,
+            span
+        }
     }
 }
-impl<'a> Display for ParseError<'a> {
+impl<'a> Display for ParseErrorKind<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Self::EndOfFile => write!(f, "Unexpected end of file"),
             Self::UnclosedSection => write!(f, "Unclosed Section, Expected `}}`"),
+            Self::UnclosedString => write!(f, "Unclosed string, Expected `\"`"),
             Self::UnexpectedCloseBracket => write!(f, "Unexpected `}}`"),
+            Self::UnexpectedToken(token) => write!(f, "Unexpected `{}`", token),
             Self::UnknownSection(section) => write!(f, "Unknown Section Kind `{}`", section),
             Self::ExpectedColon => write!(f, "Expected `:`"),
             Self::ExpectedOpenBrace => write!(f, "Expected `{{`"),
-            Self::ExpectedString => write!(f, "Expected a string")
+            Self::ExpectedString => write!(f, "Expected a string"),
+            Self::ExpectedMetadata => write!(f, "Expected text or a list of unnamed metadata, such as `!: meta {{..}}`"),
+            Self::ExpectedNumber => write!(f, "Expected a whole number greater than 0"),
+            Self::ExpectedRow => write!(f, "Expected a `row` or `header` section, as tables are made of rows"),
+            Self::ExpectedCell => write!(f, "Expected a `cell` section, as rows are made of cells"),
+            Self::InvalidAlignment(alignment) => write!(f, "Invalid alignment `{}`, expected `left`, `center` or `right`", alignment),
+            Self::InvalidDate(date) => write!(f, "Invalid date `{}`", date),
+            Self::MissingImageSource => write!(f, "Images require a source, given as the name of the section"),
+            Self::MissingReference => write!(f, "References require the section they link to, given as the name of the section"),
+            Self::UnknownAttribute(attribute) => write!(f, "Unknown Attribute `{}`", attribute),
+            Self::UnknownReference(target) => write!(f, "Reference to unknown section `{}`", target),
+            Self::InvalidReference(target) => write!(f, "Invalid reference `{}`, other files are given by the name of a ley file in the same directory, as in `file#Section`", target)
         }
     }
-}
\ No newline at end of file
+}
+
+/// A `ParseError` displayed with its file, line and column, and the offending source underlined
+pub struct Report<'a, 'b> {
+    error: &'b ParseError<'a>,
+    file: &'b str,
+    source: &'b str
+}
+impl<'a, 'b> Display for Report<'a, 'b> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let span = self.error.span;
+        let (line, column) = span.location(self.source);
+        let start = span.start.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let text = self.source[line_start..].lines().next().unwrap_or("");
+        // Only the first line of a multi-line span is underlined
+        let end = span.end.min(line_start + text.len()).max(start);
+        let underlined = &self.source[start..end];
+
+        let gutter = line.to_string().len();
+        writeln!(f, "error: {}", self.error.kind)?;
+        writeln!(f, "{:gutter$}--> {}:{}:{}", "", self.file, line, column, gutter = gutter)?;
+        writeln!(f, "{:gutter$} |", "", gutter = gutter)?;
+        writeln!(f, "{} | {}", line, text)?;
+        // Keep tabs so that the carets line up with the excerpt
+        let indent: std::string::String = self.source[line_start..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        write!(f, "{:gutter$} | {}{}", "", indent, "^".repeat(underlined.chars().count().max(1)), gutter = gutter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weekday() {
+        let weekday = |text| Date::parse(text).unwrap().format("%A");
+        assert_eq!(weekday("2024-03-01"), "Friday");
+        assert_eq!(weekday("2000-02-29"), "Tuesday");
+        assert_eq!(weekday("1970-01-01"), "Thursday");
+        // The proleptic gregorian calendar, where the year 0 is a leap year
+        assert_eq!(weekday("0000-01-01"), "Saturday");
+        assert_eq!(weekday("0000-02-29"), "Tuesday");
+        assert_eq!(weekday("0000-03-01"), "Wednesday");
+    }
+
+    #[test]
+    fn date_format() {
+        let date = Date::parse("0000-01-01").unwrap();
+        assert_eq!(date.format("%a %e %b %Y"), "Sat 1 Jan 0000");
+        assert_eq!(date.format("%Y-%m-%d"), "0000-01-01");
+        assert_eq!(date.format("100%% %q %"), "100% %q %");
+        assert_eq!(Date::parse("2023-02-29"), None);
+        assert_eq!(Date::parse("2024-13-01"), None);
+    }
+
+    /// The text of the code section or comment that `source` is made of
+    fn body(source: &str) -> std::string::String {
+        match LeyLines::new(source).unwrap().as_slice() {
+            [LeyLine::Section { contents, .. }] => contents.code(),
+            [LeyLine::Comment { body, .. }] => body.to_string(),
+            lines => panic!("Expected a single section, found {:?}", lines)
+        }
+    }
+
+    #[test]
+    fn code_bodies() {
+        assert_eq!(body(r#"!py: code { s = "" }"#), r#"s = """#);
+        assert_eq!(body(r#"!c: inline {'"'}"#), r#"'"'"#);
+        assert_eq!(body(r#"!c: code {if (x) { y(); }}"#), "if (x) { y(); }");
+        assert_eq!(body(r#"!c: code {" if (x) { y(); "}"#), "if (x) { y();");
+        assert_eq!(body(r#"!c: code {""puts("}");""}"#), r#"puts("}");"#);
+        assert_eq!(body(r#"!py: code {""" s = "" + "{" """}"#), r#"s = "" + "{""#);
+        // Quotes only fence the code when they close just before its brace
+        assert_eq!(body(r#"!c: code {"a" + "b"}"#), r#""a" + "b""#);
+        assert_eq!(body(r#"!note; {it's "unclosed}"#), r#"it's "unclosed"#);
+        assert_eq!(body("!js: code {\n    let a = !b;\n    c: d\n}"), "let a = !b;\nc: d");
+    }
+
+    #[test]
+    fn unclosed_code() {
+        let errors = LeyLines::new("!c: code {\"if (x) {").unwrap_err();
+        assert!(matches!(errors.as_slice(), [ParseError { kind: ParseErrorKind::UnclosedSection, .. }]));
+    }
+
+    /// The text of `source` with its markup written out by name, as in `emphasis(a)`
+    fn markup(source: &str) -> std::string::String {
+        fn write(lines: &LeyLines) -> std::string::String {
+            let mut text = std::string::String::new();
+            for (ley_line, spaced) in lines.spaced() {
+                match ley_line {
+                    LeyLine::Text { contents, .. } => text.push_str(&contents.verbatim().to_string()),
+                    LeyLine::Inline { kind, contents, .. } => text.push_str(&format!("{}({})", kind.name(), write(contents))),
+                    ley_line => panic!("Expected text or markup, found {:?}", ley_line)
+                }
+                if spaced {
+                    text.push(' ')
+                }
+            }
+            text
+        }
+        write(&LeyLines::new(source).unwrap())
+    }
+
+    #[test]
+    fn markup_flanking() {
+        assert_eq!(markup("*a* **b c** _d_"), "emphasis(a) strong(b c) underline(d)");
+        assert_eq!(markup("*a *b* c*"), "emphasis(a emphasis(b) c)");
+        // Markup with space on both sides neither begins nor ends anything
+        assert_eq!(markup("2 * 3 * 4"), "2 * 3 * 4");
+        assert_eq!(markup("* *"), "* *");
+        assert_eq!(markup("*a * b*"), "emphasis(a * b)");
+        assert_eq!(markup("*a *"), "*a *");
+        assert_eq!(markup("~ a~"), "~ a~");
+        // A marker ends the innermost markup of its kind, leaving any begun within it as text
+        assert_eq!(markup("*a _b* c_"), "emphasis(a _b) c_");
+        assert_eq!(markup("_x *a _b* c_"), "underline(x emphasis(a _b) c)");
+        // Hashes were never markup, as the example once suggested
+        assert_eq!(markup("#bold# **bold**"), "#bold# strong(bold)");
+    }
+
+    #[test]
+    fn unmatched_markup() {
+        // Every marker here could begin markup, but none is ever ended
+        let source = "*a _b `c ~d ".repeat(2000);
+        let start = std::time::Instant::now();
+        let lines = LeyLines::new(&source).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1), "Parsing took {:?}", start.elapsed());
+        assert!(lines.iter().all(|ley_line| matches!(ley_line, LeyLine::Text { .. })));
+        let nested = format!("{}a{}", "*a ".repeat(2000), " a*".repeat(2000));
+        assert_eq!(LeyLines::new(&nested).unwrap().len(), 1)
+    }
+}
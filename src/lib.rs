@@ -0,0 +1,38 @@
+//! Parser and renderers of Ley files
+//!
+//! ```no_run
+//! use ley::Html;
+//!
+//! let ley = ley::parse("!Heading: { Some text }").unwrap();
+//! let html = ley::render::<Html>(ley);
+//! ```
+
+pub mod ley;
+pub use crate::ley::{Date, Ley, LeyLine, LeyLines, Metadata, MetadataValue, ParseError};
+mod fmt;
+pub use fmt::{Format, Page};
+mod html;
+pub use html::Html;
+mod markdown;
+pub use markdown::Markdown;
+mod latex;
+pub use latex::Latex;
+mod text;
+pub use text::Text;
+pub mod json;
+pub use json::Json;
+mod template;
+pub use template::Template;
+mod highlight;
+mod math;
+mod pretty;
+
+/// Parse a ley file
+pub fn parse(source: &str) -> Result<Ley<'_>, Vec<ParseError<'_>>> {
+    Ley::new(source, Metadata::from(None))
+}
+
+/// Render a ley file to a string in the format `F`
+pub fn render<'a, F: Format<'a>>(ley: Ley<'a>) -> std::string::String {
+    F::from(ley).to_string()
+}
@@ -1,13 +1,6 @@
-#![feature(try_trait)]
+use std::{env, fs::{File, read_dir}, io::{self, Read, Write}, path::{Path, PathBuf}};
 
-use std::{collections::VecDeque, env, fs::{File, read_dir}, io::{Read, Write}, path::Path};
-
-pub mod ley;
-pub use ley::{Ley, LeyLine, LeyLines};
-mod fmt;
-pub use fmt::{Format, Page};
-mod html;
-use html::Html;
+use ley::{Format, Html, Json, Latex, Ley, Markdown, Metadata, MetadataValue, Page, Template, Text, json};
 
 fn main() {
     if let Some(error) = main_catch() {
@@ -46,9 +39,179 @@ macro_rules! catch {
     };
 }
 
+/// The output formats that can be chosen with `--format`
+#[derive(Clone, Copy)]
+enum OutputFormat {
+    Html,
+    Markdown,
+    Latex,
+    Text,
+    Json
+}
+impl OutputFormat {
+    fn new(name: &str) -> Option<Self> {
+        match name {
+            "html" => Some(Self::Html),
+            "markdown" | "md" => Some(Self::Markdown),
+            "latex" | "tex" => Some(Self::Latex),
+            "text" | "txt" => Some(Self::Text),
+            "json" => Some(Self::Json),
+            _ => None
+        }
+    }
+    /// The extension of files written in the format
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Html => Html::EXTENSION,
+            Self::Markdown => Markdown::EXTENSION,
+            Self::Latex => Latex::EXTENSION,
+            Self::Text => Text::EXTENSION,
+            Self::Json => Json::EXTENSION
+        }
+    }
+}
+
+/// How ley files are converted, as given on the command line
+struct Options {
+    format: OutputFormat,
+    safe: bool,
+    width: Option<usize>,
+    ansi: bool,
+    template: Option<Template>,
+    date_format: Option<String>
+}
+impl Options {
+    /// Give the ley file the date format chosen, if it doesn't choose its own
+    fn dates<'a>(&self, mut ley: Ley<'a>) -> Ley<'a> {
+        if let Some(date_format) = &self.date_format {
+            if ley.get_metadata("date-format").is_none() {
+                ley.metadata.push(("date-format".to_string(), MetadataValue::Text(date_format.clone())))
+            }
+        }
+        ley
+    }
+    fn html<'a>(&'a self, ley: Ley<'a>, pages: &'a [Page]) -> Html<'a> {
+        let html = Html::from(ley).safe(self.safe).pages(pages);
+        match &self.template {
+            Some(template) => html.template(template),
+            None => html
+        }
+    }
+    fn text<'a>(&self, ley: Ley<'a>) -> Text<'a> {
+        let text = Text::from(ley).ansi(self.ansi);
+        match self.width {
+            Some(width) => text.width(width),
+            None => text
+        }
+    }
+    /// Write the ley file to `writer` in the chosen format
+    fn write_to<W: Write>(&self, ley: Ley, writer: &mut W) -> io::Result<()> {
+        let ley = self.dates(ley);
+        match self.format {
+            OutputFormat::Html => self.html(ley, &[]).write_to(writer),
+            OutputFormat::Markdown => Markdown::from(ley).write_to(writer),
+            OutputFormat::Latex => Latex::from(ley).write_to(writer),
+            OutputFormat::Text => self.text(ley).write_to(writer),
+            OutputFormat::Json => Json::from(ley).write_to(writer)
+        }
+    }
+    /// Write the ley file to `name` in the `target` directory in the chosen format, as one of the site's `pages`
+    fn render<'a>(&'a self, ley: Ley<'a>, name: &str, target: PathBuf, pages: &'a [Page]) -> Result<Page, &'static str> {
+        let ley = self.dates(ley);
+        match self.format {
+            OutputFormat::Html => self.html(ley, pages).render(name, target),
+            OutputFormat::Markdown => Markdown::from(ley).render(name, target),
+            OutputFormat::Latex => Latex::from(ley).render(name, target),
+            OutputFormat::Text => self.text(ley).render(name, target),
+            OutputFormat::Json => Json::from(ley).render(name, target)
+        }
+    }
+    /// Write an index linking to `pages` in the `target` directory in the chosen format
+    fn index(&self, target: PathBuf, pages: &[Page], style: Metadata) -> Option<&'static str> {
+        match self.format {
+            OutputFormat::Html => self.html(self.dates(Ley::index(pages, style)), pages).render("index", target).err(),
+            OutputFormat::Markdown => Markdown::index(target, pages, style),
+            OutputFormat::Latex => Latex::index(target, pages, style),
+            OutputFormat::Text => Text::index(target, pages, style),
+            OutputFormat::Json => Json::index(target, pages, style)
+        }
+    }
+}
+
+/// `ley fmt [--check] <paths>...`, rewriting ley files, or every ley file in directories, in their canonical format.
+/// With `--check` nothing is rewritten, and it is an error for any file not to be formatted already
+fn format_files(args: impl Iterator<Item = String>) -> Option<&'static str> {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            arg if arg.starts_with("--") => {
+                eprintln!("Unexpected argument `{}`", arg);
+                return Some("Unknown option")
+            },
+            _ => paths.push(PathBuf::from(arg))
+        }
+    }
+    if paths.is_empty() {
+        return Some("A path to the file or directory to format is required")
+    }
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            for entry in catch!("Unable to read the source directory" => read_dir(&path)) {
+                let entry = catch!("Failed to iterate through directory" => entry);
+                if catch!("Unable to get file information" => entry.file_type()).is_file() && entry.path().extension().is_some_and(|extension| extension == "ley") {
+                    files.push(entry.path())
+                }
+            }
+        } else if path.is_file() {
+            files.push(path)
+        } else {
+            return Some("The source path is invalid")
+        }
+    }
+    let mut unformatted = false;
+    for path in files {
+        let mut ley_contents = String::new();
+        catch!("Unable to read from ley file" => File::open(&path).and_then(|mut file| file.read_to_string(&mut ley_contents)));
+        let formatted = match Ley::new(&ley_contents, None.into()) {
+            Ok(ley) => ley.to_string(),
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}\n", error.report(&path.to_string_lossy(), &ley_contents))
+                }
+                return Some("Errors in ley file")
+            }
+        };
+        if formatted != ley_contents {
+            if check {
+                eprintln!("{} is not formatted", path.display());
+                unformatted = true
+            } else {
+                catch!("Unable to write to ley file" => std::fs::write(&path, formatted))
+            }
+        }
+    }
+    if unformatted {
+        Some("Some files are not formatted, run `ley fmt` to format them")
+    } else {
+        None
+    }
+}
+
 fn main_catch() -> Option<&'static str> {
+    if env::args().nth(1).is_some_and(|command| command == "fmt") {
+        return format_files(env::args().skip(2))
+    }
     let mut index = false;
+    let mut safe = false;
+    let mut format = OutputFormat::Html;
+    let mut width = None;
+    let mut ansi = false;
     let mut style = None;
+    let mut template = None;
+    let mut date_format = None;
     let mut ley_source = None;
     let mut ley_destination = None;
 
@@ -57,7 +220,25 @@ fn main_catch() -> Option<&'static str> {
         while let Some(arg) = args.next() {
             match arg.as_str() {
                 "--style" => style = Some(catch!(some "style option requires an argument" => args.next())),
+                "--template" => {
+                    let path = catch!(some "template option requires an argument" => args.next());
+                    template = match Template::load(Path::new(&path)) {
+                        Ok(template) => Some(template),
+                        Err(error) => return Some(error)
+                    }
+                },
+                "--date-format" => date_format = Some(catch!(some "date format option requires an argument" => args.next())),
                 "--index" => index = true,
+                "--safe" => safe = true,
+                "--width" => {
+                    let columns = catch!(some "width option requires an argument" => args.next());
+                    width = Some(catch!("width must be a number of columns" => columns.parse()))
+                },
+                "--ansi" => ansi = true,
+                "--format" => {
+                    let name = catch!(some "format option requires an argument" => args.next());
+                    format = catch!(some "Unknown format, expected `html`, `markdown`, `latex`, `text` or `json`" => OutputFormat::new(&name))
+                },
                 arg if arg.starts_with("--") => {
                     eprintln!("Unexpected argument `{}`", arg);
                     return Some("Unknown option")
@@ -74,6 +255,7 @@ fn main_catch() -> Option<&'static str> {
             }
         }
     }
+    let options = Options { format, safe, width, ansi, template, date_format };
     let ley_source = catch!(some "A path to the file or directory to parse is required" => ley_source);
     let ley_source = Path::new(&ley_source);
     let ley_destination = ley_destination.unwrap_or(".".into());
@@ -83,7 +265,8 @@ fn main_catch() -> Option<&'static str> {
         if !ley_destination.is_dir() {
             return Some("The destination path must be a directory if the source path is a directory")
         }
-        let mut pages = vec![];
+        // Every file is read and parsed before any is written, so each page can list all the others
+        let mut sources = vec![];
         for source_path in catch!("Unable to read the source directory" => read_dir(ley_source)) {
             let source_path = catch!("Failed to iterate through directory" => source_path);
             if catch!("Unable to get file information" => source_path.file_type()).is_file() {
@@ -93,34 +276,99 @@ fn main_catch() -> Option<&'static str> {
 
                     let mut ley_contents = String::new();
                     catch!("Unable to read from ley file" => ley_source.read_to_string(&mut ley_contents));
-                    match Ley::new(&ley_contents, style.clone().into()) {
-                        Ok(ley) => {
-                            pages.push(catch!("" => Html(ley).render(file_name, ley_destination.to_path_buf())))
-                        }
-                        Err(error) => {
-                            eprintln!("Failed to parse Ley file. {}", error);
-                            return Some("Errors in ley file")
-                        }
+                    sources.push((file_name.to_string(), source_path.path(), ley_contents))
+                }
+            }
+        }
+        let mut leys = vec![];
+        for (file_name, source_path, ley_contents) in &sources {
+            match Ley::new(ley_contents, style.clone().into()) {
+                Ok(ley) => leys.push((file_name, source_path, ley_contents, ley)),
+                Err(errors) => {
+                    for error in errors {
+                        eprintln!("{}\n", error.report(&source_path.to_string_lossy(), ley_contents))
                     }
+                    return Some("Errors in ley file")
+                }
+            }
+        }
+        // References to sections are checked against every file, so a broken one fails the build
+        let files: Vec<_> = leys.iter().map(|(file_name, _, _, ley)| (file_name.as_str(), ley)).collect();
+        let mut missing = false;
+        let mut anchors = Vec::with_capacity(leys.len());
+        for (_, source_path, ley_contents, ley) in &leys {
+            match ley.resolve_references(&files) {
+                Ok(found) => anchors.push(found),
+                Err(errors) => for error in errors {
+                    eprintln!("{}\n", error.report(&source_path.to_string_lossy(), ley_contents));
+                    missing = true
                 }
             }
         }
+        if missing {
+            return Some("References to unknown sections in ley files")
+        }
+        for ((_, _, _, ley), anchors) in leys.iter_mut().zip(anchors) {
+            ley.anchors = anchors
+        }
+        let mut pages: Vec<Page> = leys.iter().map(|(file_name, _, _, ley)| Page {
+            location: format!("{}.{}", file_name, options.format.extension()),
+            title: ley.title.default("Untitled").to_string(),
+            date: ley.date.as_date()
+        }).collect();
+        // The newest pages come first, then those without a date by name
+        pages.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.location.cmp(&b.location)));
+        for (file_name, _, _, ley) in leys {
+            catch!("" => options.render(ley, file_name, ley_destination.to_path_buf(), &pages));
+        }
         if index {
-            catch!(none Html::index(ley_destination.to_path_buf(), &pages, style.into()))
+            catch!(none options.index(ley_destination.to_path_buf(), &pages, style.into()))
         }
         None
     } else if ley_source.is_file() {
+        let ley_path = ley_source.to_string_lossy();
         let mut ley_source = catch!("Unable to open source file" => File::open(ley_source));
         let mut ley_destination = catch!("Unable to create destination file" => File::create(ley_destination));
         let mut ley_contents = String::new();
         catch!("Unable to read from specified file" => ley_source.read_to_string(&mut ley_contents));
+        if ley_path.ends_with(".json") {
+            // A ley file exported as json, possibly transformed by another tool since
+            let ley_json = match json::Value::parse(&ley_contents) {
+                Ok(value) => value,
+                Err(error) => return Some(error)
+            };
+            let mut ley = match json::import(&ley_json) {
+                Ok(ley) => ley,
+                Err(error) => return Some(error)
+            };
+            if ley.style.is_none() {
+                ley.style = style.into()
+            }
+            if let Err(errors) = ley.resolve_references(&[]) {
+                for error in errors {
+                    eprintln!("{}", error)
+                }
+                return Some("References to unknown sections in ley file")
+            }
+            catch!("Unable to write to destination file" => options.write_to(ley, &mut ley_destination));
+            return None
+        }
         match Ley::new(&ley_contents, style.into()) {
             Ok(ley) => {
-                catch!("Unable to write to destination file" => write!(ley_destination, "{}", Html(ley)));
+                // Only references within the file can be checked, as the others aren't being converted
+                if let Err(errors) = ley.resolve_references(&[]) {
+                    for error in errors {
+                        eprintln!("{}\n", error.report(&ley_path, &ley_contents))
+                    }
+                    return Some("References to unknown sections in ley file")
+                }
+                catch!("Unable to write to destination file" => options.write_to(ley, &mut ley_destination));
                 None
             }
-            Err(error) => {
-                eprintln!("Failed to parse Ley file. {}", error);
+            Err(errors) => {
+                for error in errors {
+                    eprintln!("{}\n", error.report(&ley_path, &ley_contents))
+                }
                 Some("Errors in ley file")
             }
         }
@@ -0,0 +1,462 @@
+use crate::{Ley, LeyLine, LeyLines, ley::{self, MetadataValue}, html::EscapeAttribute, fmt::{Alignment, Blocks, Outline, Row, inline, is_shown, items, list_start, table}, Format};
+
+use std::{fmt::{self, Display, Formatter, Write}, ops::Deref};
+
+pub struct Markdown<'a> {
+    ley: Ley<'a>
+}
+impl<'a> Format<'a> for Markdown<'a> {
+    const EXTENSION: &'static str = "md";
+}
+impl<'a> From<Ley<'a>> for Markdown<'a> {
+    fn from(ley: Ley<'a>) -> Self {
+        Self {
+            ley
+        }
+    }
+}
+impl<'a> Display for Markdown<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Metadata is written as yaml front matter, which most markdown tools understand
+        let mut front_matter = false;
+        for (name, value) in self.ley.all_metadata() {
+            if !front_matter {
+                f.write_str("---\n")?;
+                front_matter = true
+            }
+            // Names that aren't plain words are quoted, so they can't be read as other yaml
+            if name.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-')) {
+                writeln!(f, "{}: {}", name, Yaml(value))?
+            } else {
+                writeln!(f, "{}: {}", Quoted(name), Yaml(value))?
+            }
+        }
+        if front_matter {
+            f.write_str("---")?
+        }
+        let outline = Outline::new(&self.ley);
+        let mut writer = Writer {
+            blocks: Blocks { started: front_matter, ..Blocks::new(f) },
+            outline: &outline,
+            paragraph: false
+        };
+        writer.write(&self.ley.lines, 1)?;
+        // Footnotes are defined at the end, in the syntax most markdown tools share
+        for (index, footnote) in outline.footnotes.iter().enumerate() {
+            if let LeyLine::Section { contents, .. } = footnote {
+                writer.start()?;
+                write!(writer.blocks.f, "[^{}]: {}", index + 1, BlockStart(InlineMarkdown(&outline, contents)))?
+            }
+        }
+        writer.blocks.finish()
+    }
+}
+impl<'a> Deref for Markdown<'a> {
+    type Target = Ley<'a>;
+    fn deref(&self) -> &Self::Target {
+        &self.ley
+    }
+}
+
+/// Writes ley lines as markdown blocks, joining consecutive inline lines into paragraphs
+struct Writer<'a, 'b, 'c> {
+    blocks: Blocks<'a, 'b>,
+    /// The outline of the whole document, which tables of contents list the sections of
+    outline: &'a Outline<'a, 'c>,
+    /// Whether a paragraph is open that inline lines may continue
+    paragraph: bool
+}
+impl<'a, 'b, 'c> Writer<'a, 'b, 'c> {
+    fn write(&mut self, lines: &LeyLines, depth: usize) -> fmt::Result {
+        use LeyLine::*;
+        use ley::SectionKind;
+        for (ley_line, spaced) in lines.spaced() {
+            match ley_line {
+                Section { name: Some(name), contents, kind: SectionKind::Section, .. } => {
+                    self.start()?;
+                    // Renderers make their own ids for headings, which needn't match the ones links are written with
+                    let anchor = self.outline.heading(ley_line).map_or("", |heading| &heading.anchor);
+                    write!(self.blocks.f, "{} <a id=\"{}\"></a>{}", "#".repeat(depth.min(6)), EscapeAttribute(anchor), Escape(name))?;
+                    self.write(contents, depth + 1)?;
+                    self.paragraph = false
+                },
+                Section { contents, kind: SectionKind::Paragraph, ..} | Section { name: None, contents, kind: SectionKind::Section, .. } => {
+                    self.paragraph = false;
+                    self.write(contents, depth)?;
+                    self.paragraph = false
+                },
+                Section { name, contents, kind: SectionKind::Code, .. } => {
+                    self.start()?;
+                    let code = contents.code();
+                    let fence = "`".repeat((longest_run(&code, '`') + 1).max(3));
+                    write!(self.blocks.f, "{fence}{language}\n{code}\n{fence}", fence = fence, language = name.as_ref().map(|name| name.to_string()).unwrap_or_default(), code = code)?
+                },
+                Section { contents, kind: SectionKind::Math, .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "$$\n{}\n$$", contents.code().trim())?
+                },
+                Section { name: Some(_), contents, kind: SectionKind::Image, .. } if contents.attribute("caption").is_some() => {
+                    // Markdown has no figures, so the caption follows the image on its own line
+                    self.start()?;
+                    write!(self.blocks.f, "{}\n*{}*", Line(self.outline, ley_line), Escape(contents.attribute("caption").unwrap()))?
+                },
+                Section { name, contents, kind: SectionKind::Toc, .. } => {
+                    // Markdown has no table of contents, so it is a nested list of links to the headings
+                    self.start()?;
+                    if let Some(name) = name {
+                        write!(self.blocks.f, "**{}**", Escape(name))?
+                    }
+                    for (index, heading) in self.outline.toc(contents).enumerate() {
+                        if index > 0 {
+                            self.blocks.f.write_str("\n")?
+                        } else if name.is_some() {
+                            self.blocks.f.write_str("\n\n")?
+                        }
+                        write!(self.blocks.f, "{}- [{}]({})", "  ".repeat(heading.depth - 1), Escape(heading.name), Destination(&format!("#{}", heading.anchor)))?
+                    }
+                },
+                Section { contents, kind: kind @ (SectionKind::List | SectionKind::Ordered), .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "{}", List(self.outline, *kind, contents, 0))?
+                },
+                Section { contents, kind: SectionKind::Table, .. } => {
+                    self.start()?;
+                    write!(self.blocks.f, "{}", Table(self.outline, contents))?
+                },
+                Comment { .. } | Section { kind: SectionKind::Metadata, .. } => {
+                    self.blocks.space |= spaced;
+                    continue
+                },
+                _ => {
+                    if !self.paragraph {
+                        self.start()?;
+                        self.paragraph = true;
+                        write!(self.blocks.f, "{}", BlockStart(Line(self.outline, ley_line)))?
+                    } else {
+                        if self.blocks.space {
+                            self.blocks.f.write_str(" ")?
+                        }
+                        write!(self.blocks.f, "{}", Line(self.outline, ley_line))?
+                    }
+                }
+            }
+            self.blocks.space = spaced
+        }
+        Ok(())
+    }
+    /// Begin a heading, code block, list or other block, which no paragraph continues past
+    fn start(&mut self) -> fmt::Result {
+        self.paragraph = false;
+        self.blocks.start()
+    }
+}
+
+/// A `list` or `ordered` section, indented by `indent` spaces, with the lists nested in an item indented under it
+struct List<'a>(&'a Outline<'a, 'a>, ley::SectionKind, &'a LeyLines<'a>, usize);
+impl<'a> Display for List<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let List(outline, kind, contents, indent) = *self;
+        let start = list_start(contents);
+        for (index, item) in items(contents).iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?
+            }
+            let marker = if kind == ley::SectionKind::Ordered { format!("{}.", start + index) } else { "-".to_string() };
+            write!(f, "{}{}", " ".repeat(indent), marker)?;
+            for (position, (ley_line, spaced)) in item.lines.iter().enumerate() {
+                if position == 0 {
+                    write!(f, " {}", BlockStart(Line(outline, ley_line)))?
+                } else {
+                    write!(f, "{}", Line(outline, ley_line))?
+                }
+                if *spaced && position + 1 < item.lines.len() {
+                    f.write_str(" ")?
+                }
+            }
+            for list in &item.lists {
+                if let LeyLine::Section { kind, contents, .. } = list {
+                    // Nested items line up with the text of the item they are in
+                    write!(f, "\n{}", List(outline, *kind, contents, indent + marker.len() + 1))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `table` section as a pipe table, headed by its first row if that is a header
+struct Table<'a>(&'a Outline<'a, 'a>, &'a LeyLines<'a>);
+impl<'a> Display for Table<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Table(outline, contents) = *self;
+        let table = table(contents);
+        let columns = table.columns();
+        // Markdown tables must have a head, so one without a header row is given an empty one
+        let (head, body) = match table.rows.split_first() {
+            Some((head, body)) if head.header => (Some(head), body),
+            _ => (None, &table.rows[..])
+        };
+        writeln!(f, "{}", Cells(outline, head, columns))?;
+        let delimiters: Vec<_> = table.alignments.iter().map(|alignment| match alignment {
+            Some(Alignment::Left) => ":---",
+            Some(Alignment::Center) => ":---:",
+            Some(Alignment::Right) => "---:",
+            None => "---"
+        }).collect();
+        write!(f, "| {} |", delimiters.join(" | "))?;
+        for row in body {
+            write!(f, "\n{}", Cells(outline, Some(row), columns))?
+        }
+        Ok(())
+    }
+}
+/// A row of a pipe table with `columns` cells, where a cell spanning several columns is followed by empty ones
+struct Cells<'a>(&'a Outline<'a, 'a>, Option<&'a Row<'a, 'a>>, usize);
+impl<'a> Display for Cells<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Cells(outline, row, columns) = *self;
+        let mut cells = Vec::new();
+        for (contents, span) in row.iter().flat_map(|row| &row.cells) {
+            cells.push(InlineMarkdown(outline, contents).to_string());
+            cells.resize(cells.len() + span - 1, std::string::String::new())
+        }
+        cells.resize(columns, std::string::String::new());
+        write!(f, "| {} |", cells.join(" | "))
+    }
+}
+
+/// Ley lines within a paragraph, which are all written inline, along with the outline of the whole document that footnotes are numbered through
+struct InlineMarkdown<'a>(&'a Outline<'a, 'a>, &'a LeyLines<'a>);
+impl<'a> Display for InlineMarkdown<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let InlineMarkdown(outline, lines) = *self;
+        for (ley_line, space) in inline(lines, is_shown) {
+            if space {
+                f.write_str(" ")?
+            }
+            write!(f, "{}", Line(outline, ley_line))?
+        }
+        Ok(())
+    }
+}
+
+/// A single ley line written inline, along with the outline of the whole document
+struct Line<'a>(&'a Outline<'a, 'a>, &'a LeyLine<'a>);
+impl<'a> Display for Line<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        use LeyLine::*;
+        use ley::SectionKind;
+        let Line(outline, ley_line) = *self;
+        match ley_line {
+            Text { contents, .. } => write!(f, "{}", Escape(contents)),
+            Inline { kind, contents, .. } => {
+                use ley::InlineKind;
+                let (open, close) = match kind {
+                    InlineKind::Emphasis => ("*", "*"),
+                    InlineKind::Strong => ("**", "**"),
+                    InlineKind::Underline => ("<u>", "</u>"),
+                    InlineKind::Strikethrough => ("~~", "~~"),
+                    InlineKind::Monospace => return write!(f, "{}", CodeSpan(&contents.plain().to_string()))
+                };
+                write!(f, "{}{}{}", open, InlineMarkdown(outline, contents), close)
+            },
+            Section { name: Some(name), contents, kind: SectionKind::Link, .. } => write!(f, "[{}]({})", InlineMarkdown(outline, contents), Destination(&name.to_string())),
+            Section { name: Some(name), contents, kind: SectionKind::Image, .. } => {
+                write!(f, "![{}]({}", Escape(contents.plain()), Destination(&name.to_string()))?;
+                if let Some(title) = contents.attribute("title") {
+                    write!(f, " {}", Quoted(&title.to_string()))?
+                }
+                f.write_str(")")
+            },
+            Section { contents, kind: SectionKind::Code | SectionKind::InlineCode, .. } => write!(f, "{}", CodeSpan(&contents.code())),
+            // Maths is written in the dollar signs that most markdown tools with maths support read
+            Section { contents, kind: SectionKind::Math | SectionKind::InlineMath, .. } => write!(f, "${}$", contents.code().trim()),
+            Section { kind: SectionKind::Footnote, .. } => write!(f, "[^{}]", outline.footnote_number(ley_line)),
+            Section { name: Some(target), contents, kind: SectionKind::Ref, .. } => {
+                let reference = outline.reference(target);
+                let destination = reference.url(Markdown::EXTENSION);
+                if contents.is_empty() {
+                    write!(f, "[{}]({})", Escape(&reference.name), Destination(&destination))
+                } else {
+                    write!(f, "[{}]({})", InlineMarkdown(outline, contents), Destination(&destination))
+                }
+            },
+            Section { kind: SectionKind::Image | SectionKind::Metadata | SectionKind::Toc, .. } | Comment { .. } => Ok(()),
+            Section { contents, .. } => write!(f, "{}", InlineMarkdown(outline, contents))
+        }
+    }
+}
+/// The length of the longest run of `c` in `text`
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c).map(str::len).max().unwrap_or(0)
+}
+
+/// Code within a line, fenced by enough backticks that none inside end it early
+struct CodeSpan<'a>(&'a str);
+impl<'a> Display for CodeSpan<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let fence = "`".repeat(longest_run(self.0, '`') + 1);
+        // A single space inside each end of the fence is removed again, so code may start or end with a backtick or space
+        let pad = if self.0.starts_with(['`', ' ']) || self.0.ends_with(['`', ' ']) { " " } else { "" };
+        write!(f, "{fence}{pad}{code}{pad}{fence}", fence = fence, pad = pad, code = self.0)
+    }
+}
+/// The url of a link or image, wrapped in angle brackets if it contains characters that would end it early
+struct Destination<'a>(&'a str);
+impl<'a> Display for Destination<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let url = self.0;
+        if url.contains(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '<' | '>')) {
+            write!(f, "<{}>", url.replace('<', "\\<").replace('>', "\\>"))
+        } else {
+            f.write_str(url)
+        }
+    }
+}
+/// Displays a metadata value as yaml, where dates and booleans are left bare so they are read as such
+struct Yaml<'a>(&'a MetadataValue);
+impl<'a> Display for Yaml<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            MetadataValue::Text(text) => write!(f, "{}", Quoted(text)),
+            MetadataValue::List(items) => {
+                f.write_str("[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?
+                    }
+                    write!(f, "{}", Yaml(item))?
+                }
+                f.write_str("]")
+            },
+            value => write!(f, "{}", value)
+        }
+    }
+}
+/// A double quoted string with backslash escapes, as used by yaml values and link titles
+struct Quoted<'a>(&'a str);
+impl<'a> Display for Quoted<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "\"{}\"", self.0.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Displays the inner value with the characters special to markdown escaped by a backslash
+struct Escape<T>(T);
+impl<T: Display> Display for Escape<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(Escaper(f), "{}", self.0)
+    }
+}
+/// Text that begins a block, with a `-`, `+` or number and `.` at its start escaped so it isn't read as the marker of a list item
+struct BlockStart<T>(T);
+impl<T: Display> Display for BlockStart<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let text = self.0.to_string();
+        let number = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let mut marker = text[number..].chars();
+        let marker = match (number, marker.next(), marker.next()) {
+            (0, Some('-' | '+'), None | Some(' ')) => Some(0),
+            (1.., Some('.' | ')'), None | Some(' ')) => Some(number),
+            _ => None
+        };
+        match marker {
+            Some(marker) => write!(f, "{}\\{}", &text[..marker], &text[marker..]),
+            None => f.write_str(&text)
+        }
+    }
+}
+struct Escaper<'a, 'b>(&'a mut Formatter<'b>);
+impl<'a, 'b> Write for Escaper<'a, 'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while let Some(index) = rest.find(['\\', '`', '*', '_', '[', ']', '<', '>', '#', '~', '|', '&']) {
+            self.0.write_str(&rest[..index])?;
+            self.0.write_char('\\')?;
+            self.0.write_str(&rest[index..index + 1])?;
+            rest = &rest[index + 1..];
+        }
+        self.0.write_str(rest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The markdown that the ley file `source` is written as
+    fn markdown(source: &str) -> std::string::String {
+        Markdown::from(Ley::new(source, None.into()).unwrap()).to_string()
+    }
+
+    #[test]
+    fn anchors() {
+        let markdown = markdown("!: toc {} !Intro: {} !\"Intro!\": {} !\"A. B\": {}");
+        assert!(markdown.contains("- [Intro](#intro)\n- [Intro!](#intro-2)\n- [A. B](#a-b)"));
+        assert!(markdown.contains("# <a id=\"intro\"></a>Intro\n\n# <a id=\"intro-2\"></a>Intro!\n\n# <a id=\"a-b\"></a>A. B"));
+    }
+
+    #[test]
+    fn references() {
+        let markdown = markdown("!Intro: {} !\"Intro!\": {see !\"Intro!\": ref {} and !Intro: ref {the start}}");
+        assert!(markdown.contains("# <a id=\"intro-2\"></a>Intro!\n\nsee [Intro!](#intro-2) and [the start](#intro)"));
+        assert!(markdown.contains("# <a id=\"intro\"></a>Intro\n"));
+    }
+
+    #[test]
+    fn blocks() {
+        let markdown = markdown(r#"
+!title: meta {A doc}
+!tags: meta {!: meta {one} !: meta {two}}
+!Intro: {
+    Some *emphasis* and **strong** text with a !"https://a.b/c d": link {link},
+    an !"x.png": image {alt} and !: inline {"a`b"} code.
+    !: paragraph {1. not a list}
+    !Deeper: {
+        !rust: code {"
+            let s = `'```'`;
+        "}
+    }
+}"#);
+        assert_eq!(markdown, r#"---
+title: "A doc"
+tags: ["one", "two"]
+---
+
+# <a id="intro"></a>Intro
+
+Some *emphasis* and **strong** text with a [link](<https://a.b/c d>), an ![alt](x.png) and ``a`b`` code.
+
+1\. not a list
+
+## <a id="deeper"></a>Deeper
+
+````rust
+let s = `'```'`;
+````
+"#);
+    }
+
+    #[test]
+    fn inline() {
+        assert_eq!(markdown("a * b [c] <d> ~gone~ _under_ `mono`"), "a \\* b \\[c\\] \\<d\\> ~~gone~~ <u>under</u> `mono`\n");
+    }
+
+    #[test]
+    fn footnotes() {
+        assert_eq!(markdown("a!: footnote {one !: footnote {nested}} b!: footnote {two}"), "a[^1] b[^3]\n\n[^1]: one [^2]\n\n[^2]: nested\n\n[^3]: two\n");
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(markdown("!: list {!: {Apples} one !: ordered {!start: meta {3} a !: {b}} two} !: ordered {x}"), "- Apples\n- one\n  3. a\n  4. b\n- two\n\n1. x\n");
+    }
+
+    #[test]
+    fn tables() {
+        // Markdown can't span columns, so a spanning cell is followed by empty ones
+        assert_eq!(markdown("!: table {!align: meta {left right} !: header {!: cell {Name} !: cell {Size}} !: row {!: cell {!colspan: meta {2} a | b}} !: row {!: cell {c} !: cell {*1*}}}"), "| Name | Size |\n| :--- | ---: |\n| a \\| b |  |\n| c | *1* |\n");
+        // Nor can it leave out the header
+        assert_eq!(markdown("!: table {!: row {!: cell {a} !: cell {b}}}"), "|  |  |\n| --- | --- |\n| a | b |\n");
+    }
+}
@@ -0,0 +1,347 @@
+use crate::html::EscapeAttribute;
+
+/// Commands written as a single symbol, with whether it is an identifier (`mi`) rather than an operator (`mo`)
+const SYMBOLS: &[(&str, &str, bool)] = &[
+    ("alpha", "α", true), ("beta", "β", true), ("gamma", "γ", true), ("delta", "δ", true), ("epsilon", "ϵ", true),
+    ("varepsilon", "ε", true), ("zeta", "ζ", true), ("eta", "η", true), ("theta", "θ", true), ("vartheta", "ϑ", true),
+    ("iota", "ι", true), ("kappa", "κ", true), ("lambda", "λ", true), ("mu", "μ", true), ("nu", "ν", true), ("xi", "ξ", true),
+    ("pi", "π", true), ("varpi", "ϖ", true), ("rho", "ρ", true), ("varrho", "ϱ", true), ("sigma", "σ", true),
+    ("varsigma", "ς", true), ("tau", "τ", true), ("upsilon", "υ", true), ("phi", "ϕ", true), ("varphi", "φ", true),
+    ("chi", "χ", true), ("psi", "ψ", true), ("omega", "ω", true),
+    ("Gamma", "Γ", true), ("Delta", "Δ", true), ("Theta", "Θ", true), ("Lambda", "Λ", true), ("Xi", "Ξ", true), ("Pi", "Π", true),
+    ("Sigma", "Σ", true), ("Upsilon", "Υ", true), ("Phi", "Φ", true), ("Psi", "Ψ", true), ("Omega", "Ω", true),
+    ("infty", "∞", true), ("partial", "∂", true), ("nabla", "∇", true), ("emptyset", "∅", true), ("hbar", "ℏ", true),
+    ("ell", "ℓ", true), ("Re", "ℜ", true), ("Im", "ℑ", true), ("aleph", "ℵ", true),
+    ("pm", "±", false), ("mp", "∓", false), ("times", "×", false), ("div", "÷", false), ("cdot", "⋅", false), ("ast", "∗", false),
+    ("star", "⋆", false), ("circ", "∘", false), ("bullet", "∙", false), ("leq", "≤", false), ("le", "≤", false), ("geq", "≥", false),
+    ("ge", "≥", false), ("neq", "≠", false), ("ne", "≠", false), ("approx", "≈", false), ("equiv", "≡", false), ("sim", "∼", false),
+    ("simeq", "≃", false), ("cong", "≅", false), ("propto", "∝", false), ("ll", "≪", false), ("gg", "≫", false), ("in", "∈", false),
+    ("notin", "∉", false), ("ni", "∋", false), ("subset", "⊂", false), ("supset", "⊃", false), ("subseteq", "⊆", false),
+    ("supseteq", "⊇", false), ("cup", "∪", false), ("cap", "∩", false), ("setminus", "∖", false), ("forall", "∀", false),
+    ("exists", "∃", false), ("neg", "¬", false), ("land", "∧", false), ("wedge", "∧", false), ("lor", "∨", false), ("vee", "∨", false),
+    ("to", "→", false), ("rightarrow", "→", false), ("leftarrow", "←", false), ("gets", "←", false), ("leftrightarrow", "↔", false),
+    ("Rightarrow", "⇒", false), ("Leftarrow", "⇐", false), ("Leftrightarrow", "⇔", false), ("iff", "⟺", false),
+    ("implies", "⟹", false), ("mapsto", "↦", false), ("sum", "∑", false), ("prod", "∏", false), ("coprod", "∐", false),
+    ("int", "∫", false), ("iint", "∬", false), ("oint", "∮", false), ("bigcup", "⋃", false), ("bigcap", "⋂", false),
+    ("ldots", "…", false), ("dots", "…", false), ("cdots", "⋯", false), ("vdots", "⋮", false), ("ddots", "⋱", false),
+    ("langle", "⟨", false), ("rangle", "⟩", false), ("lfloor", "⌊", false), ("rfloor", "⌋", false), ("lceil", "⌈", false),
+    ("rceil", "⌉", false), ("mid", "∣", false), ("parallel", "∥", false), ("perp", "⊥", false), ("angle", "∠", false),
+    ("prime", "′", false)
+];
+/// Functions, whose names are set upright
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh", "log", "ln", "lg", "exp",
+    "lim", "max", "min", "sup", "inf", "det", "gcd", "deg", "dim", "ker", "arg", "Pr"
+];
+/// Operators whose scripts are set under and over them in display maths, rather than beside them
+const LIMITS: &[&str] = &["sum", "prod", "coprod", "bigcup", "bigcap", "lim", "max", "min", "sup", "inf", "det", "gcd", "Pr"];
+/// Commands for space, with its width
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"), (":", "0.2222em"), (">", "0.2222em"), (";", "0.2778em"), ("!", "-0.1667em"), (" ", "0.25em"),
+    ("quad", "1em"), ("qquad", "2em")
+];
+/// Commands that set their argument in a variant of the alphabet, with the `mathvariant` of it
+const VARIANTS: &[(&str, &str)] = &[
+    ("mathbb", "double-struck"), ("mathbf", "bold"), ("mathit", "italic"), ("mathrm", "normal"), ("mathcal", "script"),
+    ("mathsf", "sans-serif"), ("mathtt", "monospace"), ("mathfrak", "fraktur")
+];
+/// The deepest groups and commands may nest, so that malicious maths can't overflow the stack
+const MAX_DEPTH: usize = 256;
+
+/// The MathML elements for the TeX-style maths `tex`, to be put in a `math` element.
+/// Display maths sets the scripts of operators such as `\sum` under and over them
+pub fn mathml(tex: &str, display: bool) -> String {
+    let tokens = tokenize(tex);
+    let mut parser = Parser { tokens: &tokens, position: 0, display, variant: None, depth: 0 };
+    let mut elements = String::new();
+    // A closing brace without an opening one ends a row early, so the rest is parsed as another
+    while parser.position < tokens.len() {
+        elements.push_str(&parser.row())
+    }
+    elements
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Token<'a> {
+    /// A command such as `\frac`, or an escaped symbol such as `\{`
+    Command(&'a str),
+    /// The argument of `\text`, which is text rather than maths
+    Text(&'a str),
+    Letter(char),
+    Number(&'a str),
+    Symbol(char),
+    Open,
+    Close,
+    Superscript,
+    Subscript
+}
+
+/// Split `tex` into tokens, leaving out whitespace
+fn tokenize(tex: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = tex.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        let token = match c {
+            '\\' => {
+                let start = index + 1;
+                let mut end = start;
+                while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphabetic()) {
+                    end += c.len_utf8()
+                }
+                if end == start {
+                    if let Some((_, c)) = chars.next() {
+                        end += c.len_utf8()
+                    }
+                }
+                let name = &tex[start..end];
+                if matches!(name, "text" | "textrm" | "mbox") {
+                    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+                    if let Some((open, _)) = chars.next_if(|(_, c)| *c == '{') {
+                        let mut depth = 1;
+                        let mut close = tex.len();
+                        for (index, c) in chars.by_ref() {
+                            match c {
+                                '{' => depth += 1,
+                                '}' => depth -= 1,
+                                _ => ()
+                            }
+                            if depth == 0 {
+                                close = index;
+                                break
+                            }
+                        }
+                        tokens.push(Token::Text(&tex[open + 1..close]));
+                        continue
+                    }
+                }
+                Token::Command(name)
+            },
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Superscript,
+            '_' => Token::Subscript,
+            c if c.is_whitespace() => continue,
+            c if c.is_ascii_digit() => {
+                let mut end = index + 1;
+                while let Some((index, _)) = chars.next_if(|(_, c)| c.is_ascii_digit() || *c == '.') {
+                    end = index + 1
+                }
+                // A full stop after a number ends a sentence, rather than starting its decimals
+                let number = &tex[index..end];
+                match number.strip_suffix('.') {
+                    Some(number) => {
+                        tokens.push(Token::Number(number));
+                        Token::Symbol('.')
+                    },
+                    None => Token::Number(number)
+                }
+            },
+            c if c.is_alphabetic() => Token::Letter(c),
+            c => Token::Symbol(c)
+        };
+        tokens.push(token)
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token<'a>],
+    position: usize,
+    display: bool,
+    /// The `mathvariant` of the letters being parsed, within a command such as `\mathbb`
+    variant: Option<&'static str>,
+    /// How many elements the one being parsed is within
+    depth: usize
+}
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.position).copied()
+    }
+    /// The elements up to the end of the tokens or a closing brace, which is passed over
+    fn row(&mut self) -> String {
+        let mut row = String::new();
+        while let Some(token) = self.peek() {
+            if token == Token::Close {
+                self.position += 1;
+                break
+            }
+            row.push_str(&self.scripted())
+        }
+        row
+    }
+    /// An element along with its subscript and superscript, if it has either
+    fn scripted(&mut self) -> String {
+        let (base, limits) = match self.peek() {
+            // Scripts without anything before them, such as those of a tensor, are on an empty base
+            Some(Token::Superscript | Token::Subscript) => ("<mrow></mrow>".to_string(), false),
+            _ => self.atom()
+        };
+        let (mut subscript, mut superscript) = (None, None);
+        loop {
+            match self.peek() {
+                Some(Token::Subscript) if subscript.is_none() => {
+                    self.position += 1;
+                    subscript = Some(self.argument())
+                },
+                Some(Token::Superscript) if superscript.is_none() => {
+                    self.position += 1;
+                    superscript = Some(self.argument())
+                },
+                _ => break
+            }
+        }
+        let (under, over, both) = if limits && self.display { ("munder", "mover", "munderover") } else { ("msub", "msup", "msubsup") };
+        match (subscript, superscript) {
+            (None, None) => base,
+            (Some(subscript), None) => format!("<{0}>{1}{2}</{0}>", under, base, subscript),
+            (None, Some(superscript)) => format!("<{0}>{1}{2}</{0}>", over, base, superscript),
+            (Some(subscript), Some(superscript)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, subscript, superscript)
+        }
+    }
+    /// The argument of a command or script: a group in braces, or a single element
+    fn argument(&mut self) -> String {
+        self.atom().0
+    }
+    /// A single element, and whether its scripts are limits set under and over it in display maths
+    fn atom(&mut self) -> (String, bool) {
+        let token = match self.peek() {
+            // A missing argument is left empty, and the closing brace it reached ends the row it is in
+            None | Some(Token::Close) => return ("<mrow></mrow>".to_string(), false),
+            Some(token) => token
+        };
+        if self.depth == MAX_DEPTH {
+            // The rest of the group is passed over, leaving the brace that closes it to end its row
+            let mut depth = 0;
+            while let Some(token) = self.peek() {
+                match token {
+                    Token::Open => depth += 1,
+                    Token::Close if depth == 0 => break,
+                    Token::Close => depth -= 1,
+                    _ => ()
+                }
+                self.position += 1
+            }
+            return ("<merror><mtext>Maths nested too deeply</mtext></merror>".to_string(), false)
+        }
+        self.position += 1;
+        self.depth += 1;
+        let atom = match token {
+            Token::Open => (format!("<mrow>{}</mrow>", self.row()), false),
+            Token::Letter(c) => (self.identifier(&c.to_string()), false),
+            Token::Number(number) => (format!("<mn>{}</mn>", number), false),
+            Token::Symbol('\'') => ("<mo>′</mo>".to_string(), false),
+            Token::Symbol('-') => ("<mo>−</mo>".to_string(), false),
+            Token::Symbol('*') => ("<mo>∗</mo>".to_string(), false),
+            Token::Symbol(c) => (format!("<mo>{}</mo>", EscapeAttribute(c)), false),
+            Token::Text(text) => (format!("<mtext>{}</mtext>", EscapeAttribute(text)), false),
+            Token::Command(name) => self.command(name),
+            // A script as the argument of another, as in `x^^`, is left empty
+            Token::Superscript | Token::Subscript | Token::Close => ("<mrow></mrow>".to_string(), false)
+        };
+        self.depth -= 1;
+        atom
+    }
+    /// An identifier, in the alphabet of any `\mathbb` or similar command it is within
+    fn identifier(&self, name: &str) -> String {
+        match self.variant {
+            Some(variant) => format!("<mi mathvariant=\"{}\">{}</mi>", variant, EscapeAttribute(name)),
+            None => format!("<mi>{}</mi>", EscapeAttribute(name))
+        }
+    }
+    /// The element for the command `\name`, and whether its scripts are limits
+    fn command(&mut self, name: &'a str) -> (String, bool) {
+        let limits = LIMITS.contains(&name);
+        if let Some((_, symbol, identifier)) = SYMBOLS.iter().find(|(command, ..)| *command == name) {
+            return (if *identifier { self.identifier(symbol) } else { format!("<mo>{}</mo>", symbol) }, limits)
+        }
+        if FUNCTIONS.contains(&name) {
+            return (format!("<mi>{}</mi>", name), limits)
+        }
+        if let Some((_, width)) = SPACES.iter().find(|(command, _)| *command == name) {
+            return (format!("<mspace width=\"{}\"></mspace>", width), false)
+        }
+        if let Some((_, variant)) = VARIANTS.iter().find(|(command, _)| *command == name) {
+            let outer = self.variant.replace(variant);
+            let argument = self.argument();
+            self.variant = outer;
+            return (argument, false)
+        }
+        match name {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                (format!("<mfrac>{}{}</mfrac>", numerator, denominator), false)
+            },
+            "sqrt" => if self.peek() == Some(Token::Symbol('[')) {
+                self.position += 1;
+                let mut index = String::new();
+                while let Some(token) = self.peek() {
+                    match token {
+                        Token::Symbol(']') => {
+                            self.position += 1;
+                            break
+                        },
+                        Token::Close => break,
+                        _ => index.push_str(&self.scripted())
+                    }
+                }
+                (format!("<mroot>{}<mrow>{}</mrow></mroot>", self.argument(), index), false)
+            } else {
+                (format!("<msqrt>{}</msqrt>", self.argument()), false)
+            },
+            // Delimiters stretch to fit what they surround anyway, and `.` is no delimiter at all
+            "left" | "right" => match self.peek() {
+                Some(Token::Symbol('.')) => {
+                    self.position += 1;
+                    (String::new(), false)
+                },
+                _ => (self.argument(), false)
+            },
+            "{" | "}" | "|" | "%" | "$" | "&" | "#" | "_" => (format!("<mo>{}</mo>", EscapeAttribute(name)), false),
+            // Maths is set on a single line
+            "\\" => (String::new(), false),
+            _ => (format!("<merror><mtext>\\{}</mtext></merror>", EscapeAttribute(name)), false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements() {
+        assert_eq!(mathml(r"\frac{a}{2}", false), "<mfrac><mrow><mi>a</mi></mrow><mrow><mn>2</mn></mrow></mfrac>");
+        assert_eq!(mathml(r"\sqrt x", false), "<msqrt><mi>x</mi></msqrt>");
+        assert_eq!(mathml(r"\sqrt[3]{x}", false), "<mroot><mrow><mi>x</mi></mrow><mrow><mn>3</mn></mrow></mroot>");
+        assert_eq!(mathml(r"\mathbb R", false), "<mi mathvariant=\"double-struck\">R</mi>");
+        assert_eq!(mathml(r"\text{a < b}", false), "<mtext>a &lt; b</mtext>");
+    }
+
+    #[test]
+    fn scripts() {
+        assert_eq!(mathml("x_i^2", false), "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>");
+        assert_eq!(mathml("x^{n+1}", false), "<msup><mi>x</mi><mrow><mi>n</mi><mo>+</mo><mn>1</mn></mrow></msup>");
+        assert_eq!(mathml("^a", false), "<msup><mrow></mrow><mi>a</mi></msup>");
+        // Limits are set under and over their operator only in display maths
+        assert_eq!(mathml(r"\sum_i", false), "<msub><mo>∑</mo><mi>i</mi></msub>");
+        assert_eq!(mathml(r"\sum_i", true), "<munder><mo>∑</mo><mi>i</mi></munder>");
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(mathml(r"a < b \& c", false), "<mi>a</mi><mo>&lt;</mo><mi>b</mi><mo>&amp;</mo><mi>c</mi>");
+        assert_eq!(mathml(r"\unknown", false), "<merror><mtext>\\unknown</mtext></merror>");
+        assert_eq!(mathml(r"\<", false), "<merror><mtext>\\&lt;</mtext></merror>");
+    }
+
+    #[test]
+    fn nesting() {
+        let nested = mathml(&r"\sqrt".repeat(200000), false);
+        assert_eq!(nested.matches("<merror>").count(), 1);
+        assert_eq!(nested.matches("<msqrt>").count(), MAX_DEPTH);
+        // Only the group nested too deeply is left out, and what follows it is kept
+        let nested = mathml(&format!("{}{}a", "{".repeat(1000), "}".repeat(1000)), false);
+        assert!(nested.contains("nested too deeply") && nested.ends_with("<mi>a</mi>"));
+    }
+}
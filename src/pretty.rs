@@ -0,0 +1,338 @@
+use crate::{Ley, LeyLine, LeyLines, ley::{self, Fragment, MetadataValue, SectionKind, Token}};
+
+use std::fmt::{self, Display, Formatter};
+
+/// The indentation of each level of nesting
+const INDENT: &str = "    ";
+/// The width that runs of text are wrapped to, including their indentation
+const WIDTH: usize = 100;
+
+impl<'a> Display for Ley<'a> {
+    /// Write the ley file back out as canonically formatted ley source:
+    /// metadata first, nested sections indented, text reflowed and words only quoted where they need to be
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut source = std::string::String::new();
+        for (name, value) in self.all_metadata() {
+            let mut run = Run::default();
+            run.words(&ley::String::split(name));
+            source.push_str(&format!("!{}: meta {{{}}}\n", run.atoms.join(" "), metadata(value)))
+        }
+        if !source.is_empty() && !self.lines.is_empty() {
+            source.push('\n')
+        }
+        write_lines(&mut source, &self.lines, 0, false);
+        f.write_str(&source)
+    }
+}
+
+/// Write each ley line of a body, at `depth` levels of indentation, or of a list or table if `items`
+fn write_lines(source: &mut std::string::String, lines: &LeyLines, depth: usize, items: bool) {
+    let mut run = Run::default();
+    let mut spaced = false;
+    for (index, (ley_line, next_spaced)) in lines.spaced().enumerate() {
+        // Comments and metadata directly against text are kept with it, so it isn't spaced where it wasn't
+        let attached = matches!(ley_line, LeyLine::Comment { .. } | LeyLine::Section { kind: SectionKind::Metadata, .. })
+            && (index > 0 && !spaced && !is_block(&lines[index - 1]) || lines.get(index + 1).is_some_and(|next| !next_spaced && !is_block(next)));
+        if is_block(ley_line) && !attached {
+            run.write(source, depth);
+            // Sections of text and code are set apart by blank lines, except for the items of a list or the rows of a table
+            let separated = !items && matches!(ley_line, LeyLine::Section { kind: SectionKind::Section | SectionKind::Paragraph | SectionKind::Code | SectionKind::Math | SectionKind::Toc | SectionKind::List | SectionKind::Ordered | SectionKind::Table, .. });
+            if separated && index > 0 && !source.ends_with("\n\n") && !source.ends_with("{\n") {
+                source.push('\n')
+            }
+            write_block(source, ley_line, depth, items);
+            if separated && index + 1 < lines.len() {
+                source.push('\n')
+            }
+        } else {
+            if spaced {
+                run.space()
+            }
+            run.line(ley_line)
+        }
+        spaced = next_spaced
+    }
+    run.write(source, depth)
+}
+
+/// Write a ley line that goes on lines of its own, as an item of a list or a row of a table if `item`
+fn write_block(source: &mut std::string::String, ley_line: &LeyLine, depth: usize, item: bool) {
+    let indent = INDENT.repeat(depth);
+    match ley_line {
+        LeyLine::Comment { name, body, source: comment, .. } => {
+            source.push_str(&indent);
+            source.push_str(&self::comment(name, body, comment));
+            source.push('\n')
+        },
+        LeyLine::Section { name, kind: kind @ (SectionKind::Code | SectionKind::InlineCode | SectionKind::Math | SectionKind::InlineMath), contents, .. } => {
+            let code = contents.code();
+            source.push_str(&format!("{}{} ", indent, header(name, *kind)));
+            if code.is_empty() {
+                source.push_str("{}\n");
+                return
+            }
+            // The code is indented with the section, which is taken off again when it is parsed
+            let mut indented = std::string::String::new();
+            for line in code.lines() {
+                if !line.is_empty() {
+                    indented.push_str(&indent);
+                    indented.push_str(INDENT)
+                }
+                indented.push_str(line);
+                indented.push('\n')
+            }
+            indented.push_str(&indent);
+            source.push_str(&format!("{{{}}}\n", fenced(&format!("\n{}", indented))))
+        },
+        LeyLine::Section { name, kind, contents, .. } => {
+            let header = header(name, *kind);
+            if contents.is_empty() {
+                source.push_str(&format!("{}{} {{}}\n", indent, header));
+                return
+            }
+            // Metadata, the items of a list and the rows of a table are short enough to keep on one line, as long as they fit
+            if *kind == SectionKind::Metadata || item && !contents.iter().any(is_block) {
+                let mut run = Run::default();
+                run.lines(contents);
+                let flat = run.atoms.join(" ");
+                if !flat.contains('\n') && indent.len() + header.len() + flat.len() + 3 <= WIDTH {
+                    source.push_str(&format!("{}{} {{{}}}\n", indent, header, flat));
+                    return
+                }
+            }
+            source.push_str(&format!("{}{} {{\n", indent, header));
+            write_lines(source, contents, depth + 1, matches!(kind, SectionKind::List | SectionKind::Ordered | SectionKind::Table));
+            source.push_str(&indent);
+            source.push_str("}\n")
+        },
+        // Only sections and comments are blocks
+        LeyLine::Text { .. } | LeyLine::Inline { .. } => ()
+    }
+}
+
+/// Whether the ley line goes on lines of its own, rather than running on with the text around it
+fn is_block(ley_line: &LeyLine) -> bool {
+    match ley_line {
+        LeyLine::Comment { .. } => true,
+        LeyLine::Section { kind, .. } => matches!(kind, SectionKind::Section | SectionKind::Paragraph | SectionKind::Metadata | SectionKind::Code | SectionKind::Math | SectionKind::Toc | SectionKind::List | SectionKind::Ordered | SectionKind::Table | SectionKind::Row | SectionKind::Header),
+        LeyLine::Text { .. } | LeyLine::Inline { .. } => false
+    }
+}
+
+/// The header of a section up to its body, leaving out the kind of named sections
+fn header(name: &Option<ley::String>, kind: SectionKind) -> std::string::String {
+    let mut run = Run::default();
+    if let Some(name) = name {
+        run.words(name)
+    }
+    let name = run.atoms.join(" ");
+    match (name.is_empty(), kind) {
+        (false, SectionKind::Section) => format!("!{}:", name),
+        (true, _) => format!("!: {}", kind.name()),
+        (false, _) => format!("!{}: {}", name, kind.name())
+    }
+}
+
+/// The body of a metadata section with the value, where the items of a list are unnamed metadata sections
+fn metadata(value: &MetadataValue) -> std::string::String {
+    match value {
+        MetadataValue::Text(text) => {
+            let mut run = Run::default();
+            run.words(&ley::String::split(text));
+            run.atoms.join(" ")
+        },
+        MetadataValue::List(items) => items.iter().map(|item| format!("!: meta {{{}}}", metadata(item))).collect::<Vec<_>>().join(" "),
+        value => value.to_string()
+    }
+}
+/// A comment as it was written, or rebuilt from its name and body if its source isn't known
+fn comment(name: &Option<ley::String>, body: &str, source: &str) -> std::string::String {
+    if !source.is_empty() {
+        return source.to_string()
+    }
+    let mut run = Run::default();
+    if let Some(name) = name {
+        run.words(name)
+    }
+    format!("!{}; {{{}}}", run.atoms.join(" "), fenced(body))
+}
+/// The body of a code section or comment fenced by as few quotes as it needs to be read back as written, which may be none
+fn fenced(body: &str) -> std::string::String {
+    let longest = body.split(|c| c != '"').map(str::len).max().unwrap_or(0);
+    for count in 0..=longest + 1 {
+        let quotes = "\"".repeat(count);
+        let fenced = format!("{quotes}{}{quotes}", body, quotes = quotes);
+        let source = format!("{{{}}}", fenced);
+        if Token::verbatim(&source, 1) == Some((body, source.len() - 1)) {
+            return fenced
+        }
+    }
+    // Quotes at either end would run into the fence, so it is spaced from them. Code is read back without the space
+    fenced(&format!(" {} ", body))
+}
+
+/// Text being laid out, as atoms that may be separated by a space or a line break but not split themselves
+#[derive(Default)]
+struct Run {
+    atoms: Vec<std::string::String>,
+    /// Where the last unquoted word is in the last atom, if nothing but markup has followed it
+    word: Option<(usize, usize)>
+}
+impl Run {
+    /// Add ley lines, spaced as they were in the source
+    fn lines(&mut self, lines: &LeyLines) {
+        let mut spaced = false;
+        for (ley_line, next_spaced) in lines.spaced() {
+            if spaced {
+                self.space()
+            }
+            self.line(ley_line);
+            spaced = next_spaced
+        }
+    }
+    /// Add a single ley line
+    fn line(&mut self, ley_line: &LeyLine) {
+        match ley_line {
+            LeyLine::Text { contents, .. } => self.words(contents),
+            LeyLine::Inline { kind, contents, .. } => {
+                self.push(kind.symbol());
+                self.lines(contents);
+                self.push(kind.symbol())
+            },
+            LeyLine::Section { name, kind: kind @ (SectionKind::Code | SectionKind::InlineCode | SectionKind::Math | SectionKind::InlineMath), contents, .. } => {
+                self.push(&format!("{} {{{}}}", header(name, *kind), fenced(&contents.code())))
+            },
+            LeyLine::Section { name, kind, contents, .. } => {
+                self.push(&header(name, *kind));
+                self.push(" {");
+                self.lines(contents);
+                self.push("}")
+            },
+            LeyLine::Comment { name, body, source, .. } => self.push(&comment(name, body, source))
+        }
+    }
+    /// Add the words of a string, quoting those that wouldn't otherwise be read back the same
+    fn words(&mut self, string: &ley::String) {
+        for fragment in string.iter() {
+            match fragment {
+                Fragment::Word("") => (),
+                Fragment::Word(word) if needs_quotes(word) => self.push(&quote(word)),
+                Fragment::Word(word) => {
+                    self.push(word);
+                    let atom = self.atoms.len() - 1;
+                    let end = self.atoms[atom].len();
+                    self.word = Some((end - word.len(), end))
+                },
+                Fragment::Space(_) => self.space()
+            }
+        }
+    }
+    /// Add text to the last atom
+    fn push(&mut self, text: &str) {
+        // Stars are read in pairs, so a single star run into more would be read as other markup, `* *` as `**`, and is kept apart
+        if self.atoms.last().is_some_and(|atom| (atom.len() - atom.trim_end_matches('*').len()) % 2 == 1) && text.starts_with('*') {
+            self.space()
+        }
+        if self.atoms.is_empty() {
+            self.atoms.push(std::string::String::new())
+        }
+        let atom = self.atoms.last_mut().unwrap();
+        // A word directly followed by markup and then more text would take in the markup, so it is quoted to end it
+        if let Some((start, end)) = self.word.take() {
+            if text.starts_with(char::is_alphanumeric) && end < atom.len() && atom[end..].chars().all(is_markup) {
+                atom.insert(end, '"');
+                atom.insert(start, '"')
+            } else if atom[end..].chars().all(is_markup) && text.chars().all(is_markup) {
+                self.word = Some((start, end))
+            }
+        }
+        atom.push_str(text)
+    }
+    /// Separate what comes next from what came before
+    fn space(&mut self) {
+        self.word = None;
+        if self.atoms.last().is_some_and(|atom| !atom.is_empty()) {
+            self.atoms.push(std::string::String::new())
+        }
+    }
+    /// Write the atoms wrapped to the width at `depth` levels of indentation, leaving the run empty
+    fn write(&mut self, source: &mut std::string::String, depth: usize) {
+        let indent = INDENT.repeat(depth);
+        let mut column = 0;
+        for atom in self.atoms.drain(..).filter(|atom| !atom.is_empty()) {
+            if column == 0 {
+                source.push_str(&indent);
+                column = indent.len()
+            } else if column + 1 + atom.len() > WIDTH {
+                source.push('\n');
+                source.push_str(&indent);
+                column = indent.len()
+            } else {
+                source.push(' ');
+                column += 1
+            }
+            source.push_str(&atom);
+            column = atom.rfind('\n').map_or(column + atom.len(), |line| atom.len() - line - 1)
+        }
+        if column > 0 {
+            source.push('\n')
+        }
+        self.word = None
+    }
+}
+
+fn is_markup(c: char) -> bool {
+    matches!(c, '*' | '_' | '`' | '~')
+}
+/// Whether a word would be read as something else if it weren't quoted
+fn needs_quotes(word: &str) -> bool {
+    if word.starts_with(is_markup) || word.contains([' ', '\n', '\r', '\t', '{', '}', '!', ':', ';', '"']) {
+        return true
+    }
+    // Markup ends a word unless the word continues after it
+    word.char_indices().any(|(index, c)| is_markup(c) && !word[index..].trim_start_matches(is_markup).starts_with(char::is_alphanumeric))
+}
+/// Quote a word. A word that is itself in quotes ends up in double quotes, which keep them
+fn quote(word: &str) -> std::string::String {
+    format!("\"{}\"", word)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Run;
+    use crate::{Html, Ley};
+
+    /// The canonical form of `source`, checking that it is settled and reads back as the same document
+    fn canonical(source: &str) -> std::string::String {
+        let ley = Ley::new(source, None.into()).unwrap();
+        let formatted = ley.to_string();
+        let reformatted = Ley::new(&formatted, None.into()).unwrap_or_else(|errors| panic!("`{}` doesn't parse: {:?}", formatted, errors));
+        assert_eq!(reformatted.to_string(), formatted);
+        assert_eq!(Html::from(reformatted).to_string(), Html::from(ley).to_string(), "`{}` renders differently", formatted);
+        formatted
+    }
+
+    #[test]
+    fn markup() {
+        assert_eq!(canonical("Rated * * out of 5"), "Rated \"*\" \"*\" out of 5\n");
+        assert_eq!(canonical("2 * 3 * 4"), "2 \"*\" 3 \"*\" 4\n");
+        assert_eq!(canonical("***a* b** and *a *b* c*"), "***a* b** and *a *b* c*\n");
+        canonical("snake_case _under_ ~gone~ `mono` and a*b* *c*d");
+        // Only a single star would pair up with the next
+        let mut run = Run::default();
+        run.push("*");
+        run.push("*");
+        run.push("**");
+        run.push("*");
+        run.push("**");
+        assert_eq!(run.atoms, ["*", "*", "***", "**"]);
+    }
+
+    #[test]
+    fn idempotent() {
+        canonical(include_str!("../tests/example.ley"));
+        canonical(include_str!("../tests/simple.ley"));
+        canonical("!title: meta {Some Title}\n!s: section {\n  Text    with  space \"quoted: words\"\n  !: list {one !: {two} three}\n}");
+        canonical("!c: code {\"\"puts(\"}\");\"\"}\n!; {a comment}\nmore text");
+    }
+}
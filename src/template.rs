@@ -0,0 +1,261 @@
+use crate::html::EscapeAttribute;
+
+use std::{fmt::{self, Display, Formatter}, fs, path::Path};
+
+/// The deepest partials may include each other, so that a partial including itself is an error rather than endless
+const MAX_PARTIAL_DEPTH: usize = 16;
+
+/// A page template, where `{{name}}` is replaced by the variable `name`.
+///
+/// - `{{#if name}}...{{else}}...{{/if}}` is the first part if the variable is set, not empty and not false, otherwise the optional second part
+/// - `{{#each name}}...{{/each}}` repeats its contents for each item of a list, with the variables of the item
+/// - `{{> name}}` includes the partial template `name.html` from the same directory
+pub struct Template {
+    nodes: Vec<Node>
+}
+impl Template {
+    /// Parse a template, with partials read from `directory`
+    pub fn parse(source: &str, directory: Option<&Path>) -> Result<Self, &'static str> {
+        let mut parser = Parser { source, directory, depth: 0 };
+        match parser.nodes()? {
+            (nodes, None) => Ok(Self { nodes }),
+            (_, Some(_)) => Err("Template has an `{{else}}` or closing tag without an opening tag")
+        }
+    }
+    /// Read and parse the template at `path`, with partials read from the same directory
+    pub fn load(path: &Path) -> Result<Self, &'static str> {
+        let source = fs::read_to_string(path).map_err(|_| "Unable to read template")?;
+        Self::parse(&source, path.parent())
+    }
+    /// Display the template filled in with `variables`
+    pub(crate) fn fill<'a>(&'a self, variables: &'a [(String, Value)]) -> Filled<'a> {
+        Filled(&self.nodes, Scope { variables, parent: None })
+    }
+}
+
+enum Node {
+    Text(String),
+    Variable(String),
+    If(String, Vec<Node>, Vec<Node>),
+    Each(String, Vec<Node>)
+}
+
+/// The value of a template variable
+pub(crate) enum Value {
+    /// Text, which is escaped when it is filled in
+    Text(String),
+    /// Html that is filled in as it is, such as the rendered page
+    Html(String),
+    Bool(bool),
+    /// Items with variables of their own, such as the pages of a site.
+    /// An item of a list of values, such as tags, is the variable `.`
+    List(Vec<Vec<(String, Value)>>)
+}
+impl Value {
+    /// Whether an `{{#if}}` of the value fills in its first part
+    fn is_set(&self) -> bool {
+        match self {
+            Self::Text(text) | Self::Html(text) => !text.is_empty(),
+            Self::Bool(value) => *value,
+            Self::List(items) => !items.is_empty()
+        }
+    }
+    fn write(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Text(text) => write!(f, "{}", EscapeAttribute(text)),
+            Self::Html(html) => f.write_str(html),
+            Self::Bool(value) => write!(f, "{}", value),
+            // A list of values is filled in with its items separated by commas
+            Self::List(items) => {
+                let values = items.iter().filter_map(|item| item.iter().find(|(name, _)| name == ".").map(|(_, value)| value));
+                for (index, value) in values.enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?
+                    }
+                    value.write(f)?
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The variables in scope, where those of an item of a list hide those around it
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    variables: &'a [(String, Value)],
+    parent: Option<&'a Scope<'a>>
+}
+impl<'a> Scope<'a> {
+    fn get(&self, name: &str) -> Option<&'a Value> {
+        self.variables.iter().rev().find(|(variable, _)| variable == name).map(|(_, value)| value)
+            .or_else(|| self.parent.and_then(|parent| parent.get(name)))
+    }
+}
+
+pub(crate) struct Filled<'a>(&'a [Node], Scope<'a>);
+impl<'a> Display for Filled<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Filled(nodes, scope) = self;
+        for node in nodes.iter() {
+            match node {
+                Node::Text(text) => f.write_str(text)?,
+                Node::Variable(name) => if let Some(value) = scope.get(name) {
+                    value.write(f)?
+                },
+                Node::If(name, then, otherwise) => {
+                    let set = scope.get(name).is_some_and(Value::is_set);
+                    write!(f, "{}", Filled(if set { then } else { otherwise }, *scope))?
+                },
+                Node::Each(name, contents) => if let Some(Value::List(items)) = scope.get(name) {
+                    for variables in items {
+                        write!(f, "{}", Filled(contents, Scope { variables, parent: Some(scope) }))?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    directory: Option<&'a Path>,
+    depth: usize
+}
+impl<'a> Parser<'a> {
+    /// Parse nodes up to the end of the source or a `{{else}}`, `{{/if}}` or `{{/each}}` tag, which is returned
+    fn nodes(&mut self) -> Result<(Vec<Node>, Option<&'a str>), &'static str> {
+        let mut nodes = Vec::new();
+        loop {
+            let start = match self.source.find("{{") {
+                Some(start) => start,
+                None => {
+                    if !self.source.is_empty() {
+                        nodes.push(Node::Text(self.source.to_string()))
+                    }
+                    self.source = "";
+                    return Ok((nodes, None))
+                }
+            };
+            if start > 0 {
+                nodes.push(Node::Text(self.source[..start].to_string()))
+            }
+            let end = self.source[start..].find("}}").ok_or("Unclosed `{{` in template")? + start;
+            let tag = self.source[start + 2..end].trim();
+            self.source = &self.source[end + 2..];
+            if let Some(name) = tag.strip_prefix("#if ") {
+                let (then, close) = self.nodes()?;
+                let otherwise = match close {
+                    Some("else") => match self.nodes()? {
+                        (otherwise, Some("/if")) => otherwise,
+                        _ => return Err("Unclosed `{{#if}}` in template")
+                    },
+                    Some("/if") => Vec::new(),
+                    _ => return Err("Unclosed `{{#if}}` in template")
+                };
+                nodes.push(Node::If(name.trim().to_string(), then, otherwise))
+            } else if let Some(name) = tag.strip_prefix("#each ") {
+                match self.nodes()? {
+                    (contents, Some("/each")) => nodes.push(Node::Each(name.trim().to_string(), contents)),
+                    _ => return Err("Unclosed `{{#each}}` in template")
+                }
+            } else if let Some(name) = tag.strip_prefix('>') {
+                nodes.extend(self.partial(name.trim())?)
+            } else if matches!(tag, "else" | "/if" | "/each") {
+                return Ok((nodes, Some(tag)))
+            } else if !tag.is_empty() && tag.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.')) {
+                nodes.push(Node::Variable(tag.to_string()))
+            } else {
+                return Err("Unknown tag in template")
+            }
+        }
+    }
+    /// The nodes of the partial template `name`
+    fn partial(&mut self, name: &str) -> Result<Vec<Node>, &'static str> {
+        if self.depth == MAX_PARTIAL_DEPTH {
+            return Err("Partials in template are included too deeply")
+        }
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err("Invalid partial name in template")
+        }
+        let directory = self.directory.ok_or("Partials can only be used in templates loaded from a file")?;
+        let source = fs::read_to_string(directory.join(format!("{}.html", name))).map_err(|_| "Unable to read partial template")?;
+        let mut parser = Parser { source: &source, directory: self.directory, depth: self.depth + 1 };
+        match parser.nodes()? {
+            (nodes, None) => Ok(nodes),
+            (_, Some(_)) => Err("Partial template has an `{{else}}` or closing tag without an opening tag")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `source` filled in with `variables`
+    fn fill(source: &str, variables: &[(String, Value)]) -> String {
+        Template::parse(source, None).unwrap().fill(variables).to_string()
+    }
+    fn text(name: &str, value: &str) -> (String, Value) {
+        (name.to_string(), Value::Text(value.to_string()))
+    }
+
+    #[test]
+    fn variables() {
+        let variables = [text("title", "<A & \"B\">"), ("content".to_string(), Value::Html("<p>a</p>".to_string()))];
+        assert_eq!(fill("<title>{{ title }}</title>{{content}}{{missing}}", &variables), "<title>&lt;A &amp; &quot;B&quot;&gt;</title><p>a</p>");
+        let tags = ("tags".to_string(), Value::List(vec![vec![text(".", "a")], vec![text(".", "<b>")]]));
+        assert_eq!(fill("{{tags}}", &[tags]), "a, &lt;b&gt;");
+    }
+
+    #[test]
+    fn conditionals() {
+        let variables = [text("title", "A"), text("empty", ""), ("draft".to_string(), Value::Bool(false)), ("pages".to_string(), Value::List(Vec::new()))];
+        let source = "{{#if title}}[{{title}}]{{/if}}{{#if empty}}e{{else}}not e{{/if}}{{#if draft}}d{{else}}{{#if pages}}p{{else}}no p{{/if}}{{/if}}{{#if missing}}m{{/if}}";
+        assert_eq!(fill(source, &variables), "[A]not eno p");
+    }
+
+    #[test]
+    fn each() {
+        let pages = ("pages".to_string(), Value::List(vec![vec![text("title", "One")], vec![text("location", "two.html")]]));
+        let variables = [text("title", "Site"), text("location", "index.html"), pages];
+        // The variables of an item hide those of the page, which are still in scope where the item has none of its own
+        assert_eq!(fill("{{#each pages}}{{title}} at {{location}}; {{/each}}{{title}}", &variables), "One at index.html; Site at two.html; Site");
+        assert_eq!(fill("{{#each title}}x{{/each}}{{#each missing}}x{{/each}}", &variables), "");
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| Template::parse(source, None).err();
+        assert_eq!(error("{{title"), Some("Unclosed `{{` in template"));
+        assert_eq!(error("{{#if a}}b"), Some("Unclosed `{{#if}}` in template"));
+        assert_eq!(error("{{#if a}}b{{/each}}"), Some("Unclosed `{{#if}}` in template"));
+        assert_eq!(error("{{#each a}}b{{/if}}"), Some("Unclosed `{{#each}}` in template"));
+        assert_eq!(error("a{{/if}}"), Some("Template has an `{{else}}` or closing tag without an opening tag"));
+        assert_eq!(error("{{a b}}"), Some("Unknown tag in template"));
+        assert_eq!(error("{{> header}}"), Some("Partials can only be used in templates loaded from a file"));
+    }
+
+    #[test]
+    fn partials() {
+        let directory = std::env::temp_dir().join(format!("ley-partials-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("header.html"), "<h1>{{title}}</h1>{{> nav}}").unwrap();
+        fs::write(directory.join("nav.html"), "{{#each pages}}<a>{{title}}</a>{{/each}}").unwrap();
+        fs::write(directory.join("itself.html"), "{{> itself}}").unwrap();
+        fs::write(directory.join("unclosed.html"), "{{/each}}").unwrap();
+        fs::write(directory.join("page.html"), "{{> header}}{{content}}").unwrap();
+        let template = Template::load(&directory.join("page.html")).unwrap();
+        let pages = ("pages".to_string(), Value::List(vec![vec![text("title", "One")]]));
+        assert_eq!(template.fill(&[text("title", "Site"), text("content", "text"), pages]).to_string(), "<h1>Site</h1><a>One</a>text");
+        let error = |source: &str| Template::parse(source, Some(&directory)).err();
+        assert_eq!(error("{{> itself}}"), Some("Partials in template are included too deeply"));
+        assert_eq!(error("{{> unclosed}}"), Some("Partial template has an `{{else}}` or closing tag without an opening tag"));
+        assert_eq!(error("{{> missing}}"), Some("Unable to read partial template"));
+        for name in ["../page", ".hidden", "a\\b", ""] {
+            assert_eq!(error(&format!("{{{{> {}}}}}", name)), Some("Invalid partial name in template"))
+        }
+        fs::remove_dir_all(&directory).unwrap()
+    }
+}
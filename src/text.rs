@@ -0,0 +1,523 @@
+use crate::{Ley, LeyLine, LeyLines, ley, Format, fmt::{Alignment, Blocks, Outline, inline, is_shown, items, list_start, table}, highlight::{highlight, Class}};
+
+use std::{fmt::{self, Display, Formatter}, ops::Deref};
+
+pub struct Text<'a> {
+    ley: Ley<'a>,
+    width: usize,
+    ansi: bool
+}
+impl<'a> Text<'a> {
+    /// Wrap paragraphs to `width` columns, or not at all if it is 0. Defaults to 80
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+    /// In ansi mode, headings and inline markup are styled and code is highlighted with terminal escape codes,
+    /// for viewing in a terminal or a pager such as `less -R`
+    pub fn ansi(mut self, ansi: bool) -> Self {
+        self.ansi = ansi;
+        self
+    }
+    /// Surround `text` with the escape codes of `style` in ansi mode
+    fn style(&self, (on, off): Style, text: &str) -> std::string::String {
+        if self.ansi {
+            format!("\x1b[{}m{}\x1b[{}m", on, text, off)
+        } else {
+            text.to_string()
+        }
+    }
+}
+impl<'a> Format<'a> for Text<'a> {
+    const EXTENSION: &'static str = "txt";
+}
+impl<'a> From<Ley<'a>> for Text<'a> {
+    fn from(ley: Ley<'a>) -> Self {
+        Self {
+            ley,
+            width: 80,
+            ansi: false
+        }
+    }
+}
+impl<'a> Display for Text<'a> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut writer = Writer {
+            text: self,
+            outline: Outline::new(&self.ley),
+            blocks: Blocks::new(f),
+            paragraph: std::string::String::new(),
+            numbers: Vec::new(),
+            references: Vec::new()
+        };
+        if let Some(title) = self.title.as_ref() {
+            let title = self.show(title);
+            writer.blocks.start()?;
+            write!(writer.blocks.f, "{}\n{}", self.style(BOLD, &title), "=".repeat(title.chars().count()))?;
+            let byline: Vec<_> = [&self.author, &self.date].iter().filter_map(|metadata| metadata.as_ref().map(|value| self.show(value))).collect();
+            if !byline.is_empty() {
+                write!(writer.blocks.f, "\n{}", byline.join(", "))?
+            }
+        }
+        writer.write(&self.ley.lines, 1)?;
+        writer.flush()?;
+        for (index, footnote) in writer.outline.footnotes.clone().iter().enumerate() {
+            if let LeyLine::Section { contents, .. } = footnote {
+                writer.paragraph = format!("[^{}] ", index + 1);
+                writer.inline_lines(contents);
+                writer.flush()?
+            }
+        }
+        if !writer.references.is_empty() {
+            writer.blocks.start()?;
+            for (index, url) in writer.references.iter().enumerate() {
+                if index > 0 {
+                    writer.blocks.f.write_str("\n")?
+                }
+                write!(writer.blocks.f, "[{}] {}", index + 1, url)?
+            }
+        }
+        writer.blocks.finish()
+    }
+}
+impl<'a> Deref for Text<'a> {
+    type Target = Ley<'a>;
+    fn deref(&self) -> &Self::Target {
+        &self.ley
+    }
+}
+
+/// The escape codes turning a style on and off
+type Style = (&'static str, &'static str);
+const BOLD: Style = ("1", "22");
+const ITALIC: Style = ("3", "23");
+const UNDERLINE: Style = ("4", "24");
+const STRIKETHROUGH: Style = ("9", "29");
+
+/// The indentation of code blocks
+const INDENT: &str = "    ";
+/// What separates the columns of a table
+const SEPARATOR: &str = " | ";
+
+/// Writes ley lines as plain text blocks.
+/// Consecutive inline lines are collected into a paragraph, which is wrapped once it ends
+struct Writer<'a, 'b, 'c> {
+    text: &'a Text<'a>,
+    /// The outline of the whole document, which tables of contents and references number the sections of
+    outline: Outline<'a, 'a>,
+    blocks: Blocks<'b, 'c>,
+    /// The text of the paragraph being collected
+    paragraph: std::string::String,
+    /// The number of the current heading at each depth
+    numbers: Vec<usize>,
+    /// The urls of links and images, referred to by their position counting from 1
+    references: Vec<std::string::String>
+}
+impl<'a, 'b, 'c> Writer<'a, 'b, 'c> {
+    fn write(&mut self, lines: &LeyLines, depth: usize) -> fmt::Result {
+        use LeyLine::{Section, Comment};
+        use ley::SectionKind;
+        for (ley_line, spaced) in lines.spaced() {
+            match ley_line {
+                Section { name: Some(name), contents, kind: SectionKind::Section, .. } => {
+                    self.flush()?;
+                    self.blocks.start()?;
+                    self.numbers.resize(depth, 0);
+                    self.numbers[depth - 1] += 1;
+                    let number: Vec<_> = self.numbers.iter().map(|number| number.to_string()).collect();
+                    let heading = format!("{} {}", number.join("."), name);
+                    self.blocks.f.write_str(&self.text.style(BOLD, &heading))?;
+                    if depth == 1 {
+                        write!(self.blocks.f, "\n{}", "-".repeat(heading.chars().count()))?
+                    }
+                    self.write(contents, depth + 1)?;
+                    self.flush()?
+                },
+                Section { contents, kind: SectionKind::Paragraph, ..} | Section { name: None, contents, kind: SectionKind::Section, .. } => {
+                    self.flush()?;
+                    self.write(contents, depth)?;
+                    self.flush()?
+                },
+                Section { name, contents, kind: SectionKind::Code, .. } => {
+                    self.flush()?;
+                    self.blocks.start()?;
+                    self.code(name, contents)?
+                },
+                // Maths can't be typeset in text, so it is shown as the TeX it was written in, like code
+                Section { contents, kind: SectionKind::Math, .. } => {
+                    self.flush()?;
+                    self.blocks.start()?;
+                    self.code(&None, contents)?
+                },
+                Section { name: Some(_), contents, kind: SectionKind::Image, .. } if contents.attribute("caption").is_some() => {
+                    self.flush()?;
+                    self.inline(ley_line);
+                    self.flush()?;
+                    self.blocks.f.write_str("\n")?;
+                    self.wrap(&contents.attribute("caption").unwrap().to_string())?
+                },
+                Section { name, contents, kind: SectionKind::Toc, .. } => {
+                    self.flush()?;
+                    self.blocks.start()?;
+                    let mut entries: Vec<_> = name.iter().map(|name| self.text.style(BOLD, &name.to_string())).collect();
+                    for heading in self.outline.toc(contents) {
+                        let number: Vec<_> = heading.number.iter().map(|number| number.to_string()).collect();
+                        entries.push(format!("{}{} {}", "  ".repeat(heading.depth - 1), number.join("."), heading.name))
+                    }
+                    self.blocks.f.write_str(&entries.join("\n"))?
+                },
+                Section { contents, kind: kind @ (SectionKind::List | SectionKind::Ordered), .. } => {
+                    self.flush()?;
+                    self.blocks.start()?;
+                    self.list(*kind, contents, 0)?
+                },
+                Section { contents, kind: SectionKind::Table, .. } => {
+                    self.flush()?;
+                    self.blocks.start()?;
+                    self.table(contents)?
+                },
+                Comment { .. } | Section { kind: SectionKind::Metadata, .. } => {
+                    self.blocks.space |= spaced;
+                    continue
+                },
+                _ => {
+                    if !self.paragraph.is_empty() && self.blocks.space {
+                        self.paragraph.push(' ')
+                    }
+                    self.inline(ley_line)
+                }
+            }
+            self.blocks.space = spaced
+        }
+        Ok(())
+    }
+    /// Add ley lines to the paragraph, spaced as they were in the source
+    fn inline_lines(&mut self, lines: &LeyLines) {
+        for (ley_line, space) in inline(lines, is_shown) {
+            if space {
+                self.paragraph.push(' ')
+            }
+            self.inline(ley_line)
+        }
+    }
+    /// Add a single ley line to the paragraph
+    fn inline(&mut self, ley_line: &LeyLine) {
+        use LeyLine::{Section, Inline, Comment};
+        use ley::{SectionKind, InlineKind};
+        match ley_line {
+            LeyLine::Text { contents, .. } => self.paragraph.push_str(&contents.to_string()),
+            Inline { kind, contents, .. } => {
+                let style = match kind {
+                    InlineKind::Emphasis => Some(ITALIC),
+                    InlineKind::Strong => Some(BOLD),
+                    InlineKind::Underline => Some(UNDERLINE),
+                    InlineKind::Strikethrough => Some(STRIKETHROUGH),
+                    // Terminals are already monospace, so it is marked the same either way
+                    InlineKind::Monospace => None
+                };
+                let (open, close) = match style {
+                    Some((on, off)) if self.text.ansi => (format!("\x1b[{}m", on), format!("\x1b[{}m", off)),
+                    _ => (kind.symbol().to_string(), kind.symbol().to_string())
+                };
+                self.paragraph.push_str(&open);
+                self.inline_lines(contents);
+                self.paragraph.push_str(&close)
+            },
+            Section { name: Some(name), contents, kind: SectionKind::Link, .. } => {
+                self.inline_lines(contents);
+                let reference = self.reference(name.to_string());
+                self.paragraph.push_str(&format!("[{}]", reference))
+            },
+            Section { name: Some(name), contents, kind: SectionKind::Image, .. } => {
+                let alt = contents.plain().to_string();
+                let reference = self.reference(name.to_string());
+                if alt.is_empty() {
+                    self.paragraph.push_str(&format!("[image][{}]", reference))
+                } else {
+                    self.paragraph.push_str(&format!("[image: {}][{}]", alt, reference))
+                }
+            },
+            Section { contents, kind: SectionKind::Code | SectionKind::InlineCode, .. } => self.paragraph.push_str(&format!("`{}`", contents.code())),
+            Section { contents, kind: SectionKind::Math | SectionKind::InlineMath, .. } => self.paragraph.push_str(&format!("`{}`", contents.code().trim())),
+            Section { kind: SectionKind::Footnote, .. } => {
+                let number = self.outline.footnote_number(ley_line);
+                self.paragraph.push_str(&format!("[^{}]", number))
+            },
+            Section { name: Some(target), contents, kind: SectionKind::Ref, .. } => {
+                let target = self.outline.reference(target);
+                if contents.is_empty() {
+                    self.paragraph.push_str(&target.name)
+                } else {
+                    self.inline_lines(contents)
+                }
+                // A section of this file is referred to by its number, and one of another file like a link
+                let number = self.outline.referenced(&target.name).filter(|_| target.file.is_none())
+                    .map(|heading| heading.number.iter().map(|number| number.to_string()).collect::<Vec<_>>().join("."));
+                match number {
+                    Some(number) => self.paragraph.push_str(&format!(" (see {})", number)),
+                    None => {
+                        let reference = self.reference(target.url(Text::EXTENSION));
+                        self.paragraph.push_str(&format!("[{}]", reference))
+                    }
+                }
+            },
+            Section { kind: SectionKind::Image | SectionKind::Metadata | SectionKind::Toc, .. } | Comment { .. } => (),
+            Section { contents, .. } => self.inline_lines(contents)
+        }
+    }
+    /// The number of the reference to `url`, adding it if it hasn't been referred to before
+    fn reference(&mut self, url: std::string::String) -> usize {
+        match self.references.iter().position(|reference| *reference == url) {
+            Some(index) => index + 1,
+            None => {
+                self.references.push(url);
+                self.references.len()
+            }
+        }
+    }
+    /// Write a code section indented, and highlighted in ansi mode if the language is known
+    fn code(&mut self, name: &Option<ley::String>, contents: &LeyLines) -> fmt::Result {
+        let code = contents.code();
+        let pieces = match name {
+            Some(language) if self.text.ansi => highlight(&language.to_string(), &code),
+            _ => None
+        };
+        let mut line_start = true;
+        for (class, piece) in pieces.unwrap_or_else(|| vec![(None, code.as_str())]) {
+            for segment in piece.split_inclusive('\n') {
+                // Blank lines are left without indentation so they have no trailing whitespace
+                if line_start && segment != "\n" {
+                    self.blocks.f.write_str(INDENT)?
+                }
+                match class.and_then(colour) {
+                    Some(colour) => write!(self.blocks.f, "\x1b[{}m{}\x1b[39m", colour, segment)?,
+                    None => self.blocks.f.write_str(segment)?
+                }
+                line_start = segment.ends_with('\n')
+            }
+        }
+        Ok(())
+    }
+    /// Write the paragraph being collected, if there is one
+    fn flush(&mut self) -> fmt::Result {
+        if !self.paragraph.is_empty() {
+            let paragraph = std::mem::take(&mut self.paragraph);
+            self.blocks.start()?;
+            self.wrap(&paragraph)?
+        }
+        Ok(())
+    }
+    /// Write a `list` or `ordered` section indented by `indent` columns, with the lists nested in an item indented under it
+    fn list(&mut self, kind: ley::SectionKind, contents: &LeyLines, indent: usize) -> fmt::Result {
+        let start = list_start(contents);
+        for (index, item) in items(contents).iter().enumerate() {
+            if index > 0 {
+                self.blocks.f.write_str("\n")?
+            }
+            for (position, (ley_line, spaced)) in item.lines.iter().enumerate() {
+                self.inline(ley_line);
+                if *spaced && position + 1 < item.lines.len() {
+                    self.paragraph.push(' ')
+                }
+            }
+            let text = std::mem::take(&mut self.paragraph);
+            let marker = if kind == ley::SectionKind::Ordered { format!("{}. ", start + index) } else { "- ".to_string() };
+            self.hang(&text, &format!("{}{}", " ".repeat(indent), marker), indent + marker.len())?;
+            for list in &item.lists {
+                if let LeyLine::Section { kind, contents, .. } = list {
+                    self.blocks.f.write_str("\n")?;
+                    self.list(*kind, contents, indent + marker.len())?
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Write a `table` section with its columns padded to line up, separated by `|`, and a rule under its header rows
+    fn table(&mut self, contents: &LeyLines) -> fmt::Result {
+        let table = table(contents);
+        let mut rows = Vec::new();
+        for row in &table.rows {
+            let mut cells = Vec::new();
+            for (contents, span) in &row.cells {
+                self.inline_lines(contents);
+                let text = std::mem::take(&mut self.paragraph);
+                cells.push((if row.header { self.text.style(BOLD, &text) } else { text }, *span))
+            }
+            rows.push(cells)
+        }
+        // Columns are as wide as their widest cell, and then widened for any wider cell spanning them
+        let mut widths = vec![0; table.columns()];
+        for spanning in [false, true] {
+            for cells in &rows {
+                let mut column = 0;
+                for (text, span) in cells {
+                    let length = visible_length(text);
+                    if (*span > 1) == spanning {
+                        let width = widths[column..column + span].iter().sum::<usize>() + SEPARATOR.len() * (span - 1);
+                        widths[column + span - 1] += length.saturating_sub(width)
+                    }
+                    column += span
+                }
+            }
+        }
+        for (index, cells) in rows.iter().enumerate() {
+            if index > 0 {
+                self.blocks.f.write_str("\n")?
+            }
+            let mut line = std::string::String::new();
+            let mut column = 0;
+            for (text, span) in cells {
+                if column > 0 {
+                    line.push_str(SEPARATOR)
+                }
+                let width = widths[column..column + span].iter().sum::<usize>() + SEPARATOR.len() * (span - 1);
+                let space = width - visible_length(text);
+                let (before, after) = match table.alignments[column] {
+                    Some(Alignment::Right) => (space, 0),
+                    Some(Alignment::Center) => (space / 2, space - space / 2),
+                    Some(Alignment::Left) | None => (0, space)
+                };
+                line.push_str(&format!("{}{}{}", " ".repeat(before), text, " ".repeat(after)));
+                column += span
+            }
+            self.blocks.f.write_str(line.trim_end())?;
+            if table.rows[index].header && table.rows.get(index + 1).is_some_and(|next| !next.header) {
+                let rule: Vec<_> = widths.iter().map(|width| "-".repeat(*width)).collect();
+                write!(self.blocks.f, "\n{}", rule.join("-+-"))?
+            }
+        }
+        Ok(())
+    }
+    /// Write `text` with its words wrapped to the width
+    fn wrap(&mut self, text: &str) -> fmt::Result {
+        self.hang(text, "", 0)
+    }
+    /// Write `first` followed by `text` with its words wrapped to the width, indenting the lines after the first by `indent` columns
+    fn hang(&mut self, text: &str, first: &str, indent: usize) -> fmt::Result {
+        self.blocks.f.write_str(first)?;
+        let mut column = visible_length(first);
+        let mut line_start = true;
+        for word in text.split(' ').filter(|word| !word.is_empty()) {
+            let length = visible_length(word);
+            if !line_start && self.text.width > 0 && column + 1 + length > self.text.width {
+                write!(self.blocks.f, "\n{}", " ".repeat(indent))?;
+                column = indent
+            } else if !line_start {
+                self.blocks.f.write_str(" ")?;
+                column += 1
+            }
+            self.blocks.f.write_str(word)?;
+            column += length;
+            line_start = false
+        }
+        Ok(())
+    }
+}
+/// The number of characters of `text` shown in a terminal, leaving out escape codes
+fn visible_length(text: &str) -> usize {
+    let mut length = 0;
+    let mut escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => escape = true,
+            'm' if escape => escape = false,
+            _ if escape => (),
+            _ => length += 1
+        }
+    }
+    length
+}
+/// The ansi foreground colour code of highlighted code
+fn colour(class: Class) -> Option<&'static str> {
+    match class {
+        Class::Keyword => Some("35"),
+        Class::Type => Some("33"),
+        Class::Literal | Class::Number => Some("36"),
+        Class::String => Some("32"),
+        Class::Comment => Some("90"),
+        Class::Variable => Some("31"),
+        Class::Punctuation => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"
+!title: meta {Doc}
+!author: meta {Ann}
+!Intro: {
+    A paragraph long enough that it has to be wrapped at the width of twenty columns, with a !"https://a.b": link {link}.
+    !Deeper: {
+        !rust: code {"
+            fn x() {}
+        "}
+        *em* text
+    }
+}"#;
+
+    /// The text that `SOURCE` is written as
+    fn text(width: usize, ansi: bool) -> std::string::String {
+        Text::from(Ley::new(SOURCE, None.into()).unwrap()).width(width).ansi(ansi).to_string()
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(text(20, false), "Doc
+===
+Ann
+
+1 Intro
+-------
+
+A paragraph long
+enough that it has
+to be wrapped at the
+width of twenty
+columns, with a
+link[1].
+
+1.1 Deeper
+
+    fn x() {}
+
+*em* text
+
+[1] https://a.b
+");
+    }
+
+    #[test]
+    fn ansi() {
+        assert_eq!(text(0, true), "\x1b[1mDoc\x1b[22m
+===
+Ann
+
+\x1b[1m1 Intro\x1b[22m
+-------
+
+A paragraph long enough that it has to be wrapped at the width of twenty columns, with a link[1].
+
+\x1b[1m1.1 Deeper\x1b[22m
+
+    \x1b[35mfn\x1b[39m x() {}
+
+\x1b[3mem\x1b[23m text
+
+[1] https://a.b
+");
+    }
+
+    #[test]
+    fn lists() {
+        let text = Text::from(Ley::new("!: list {!: {Apples} one !: ordered {!start: meta {3} a !: {b}} two} !: ordered {x}", None.into()).unwrap()).to_string();
+        assert_eq!(text, "- Apples\n- one\n  3. a\n  4. b\n- two\n\n1. x\n");
+    }
+
+    #[test]
+    fn tables() {
+        let text = Text::from(Ley::new("!: table {!align: meta {left right} !: header {!: cell {Name} !: cell {Size}} !: row {!: cell {!colspan: meta {2} a | b}} !: row {!: cell {c} !: cell {*1*}}}", None.into()).unwrap()).to_string();
+        assert_eq!(text, "Name | Size\n-----+-----\na | b\nc    |  *1*\n");
+    }
+}